@@ -0,0 +1,85 @@
+use crate::agent::{check_peer, check_socket_dir, Request, Response};
+use crate::db::kdf::Key;
+use crate::TotpError;
+use data_encoding::BASE64;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Client {
+    socket: PathBuf,
+}
+
+impl Client {
+    pub fn new(socket: PathBuf) -> Self {
+        Self { socket }
+    }
+
+    /// The key of an unlocked vault, `None` when the agent does not hold it
+    pub fn key(&self, vault: &str) -> Result<Option<Key>, TotpError> {
+        match self.send(&Request::Key {
+            vault: vault.to_string(),
+        })? {
+            Response::Key { key } => {
+                let key = Zeroizing::new(key);
+                Ok(Some(Key::from_bytes(BASE64.decode(key.as_bytes())?)?))
+            }
+            Response::Locked => Ok(None),
+            response => Err(Self::unexpected(response)),
+        }
+    }
+
+    pub fn unlock(&self, vault: &str, key: &Key) -> Result<(), TotpError> {
+        match self.send(&Request::Unlock {
+            vault: vault.to_string(),
+            key: BASE64.encode(key.as_bytes()),
+        })? {
+            Response::Ok => Ok(()),
+            response => Err(Self::unexpected(response)),
+        }
+    }
+
+    /// Forget the key of `vault`, or of every vault
+    pub fn lock(&self, vault: Option<&str>) -> Result<(), TotpError> {
+        match self.send(&Request::Lock {
+            vault: vault.map(str::to_string),
+        })? {
+            Response::Ok => Ok(()),
+            response => Err(Self::unexpected(response)),
+        }
+    }
+
+    fn send(&self, request: &Request) -> Result<Response, TotpError> {
+        if let Some(dir) = self.socket.parent() {
+            check_socket_dir(dir)?;
+        }
+        let stream = UnixStream::connect(&self.socket).map_err(|e| {
+            TotpError::Agent(format!(
+                "Cannot reach the agent on {}: {}",
+                self.socket.display(),
+                e
+            ))
+        })?;
+        // Only an agent run by the same user may be handed keys
+        check_peer(&stream)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let request = Zeroizing::new(serde_json::to_string(request)?);
+        (&stream).write_all(request.as_bytes())?;
+        (&stream).write_all(b"\n")?;
+        let mut line = Zeroizing::new(String::new());
+        BufReader::new(&stream).read_line(&mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    fn unexpected(response: Response) -> TotpError {
+        match response {
+            Response::Error { message } => TotpError::Agent(message),
+            _ => TotpError::Agent("Unexpected response from the agent".to_string()),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::TotpError;
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+pub mod client;
+pub mod server;
+
+/// Forget unlocked vaults after 15 minutes without use
+pub const DEFAULT_TIMEOUT: u64 = 15 * 60;
+
+/// One JSON line sent to the agent per connection
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Hold the derived key of a vault
+    Unlock { vault: String, key: String },
+    /// Forget the key of one vault, or of every vault when `None`
+    Lock { vault: Option<String> },
+    /// Ask for the key of an unlocked vault
+    Key { vault: String },
+}
+
+/// The agent's one JSON line reply
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Key { key: String },
+    Locked,
+    Error { message: String },
+}
+
+/// `$XDG_RUNTIME_DIR/trotp/agent.sock`, or a per user directory in the temp dir
+pub fn default_socket() -> PathBuf {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join("trotp"),
+        None => std::env::temp_dir().join(format!(
+            "trotp-{}",
+            std::env::var("USER").unwrap_or_default()
+        )),
+    };
+    dir.join("agent.sock")
+}
+
+/// Refuse a socket directory that another user created or can reach into
+///
+/// Otherwise someone who made `/tmp/trotp-$USER` first could listen there and be handed keys.
+pub fn check_socket_dir(dir: &Path) -> Result<(), TotpError> {
+    let metadata = std::fs::symlink_metadata(dir)
+        .map_err(|e| TotpError::Agent(format!("Cannot use {}: {}", dir.display(), e)))?;
+    if !metadata.is_dir() {
+        return Err(TotpError::Agent(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+    if metadata.uid() != current_uid() {
+        return Err(TotpError::Agent(format!(
+            "{} belongs to another user",
+            dir.display()
+        )));
+    }
+    if metadata.mode() & 0o777 != 0o700 {
+        return Err(TotpError::Agent(format!(
+            "{} must only be accessible by its owner, run chmod 700 on it",
+            dir.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Refuse connections from processes of other users
+pub fn check_peer(stream: &UnixStream) -> Result<(), TotpError> {
+    let uid = peer_uid(stream)?;
+    if uid != current_uid() {
+        return Err(TotpError::Agent(format!(
+            "Refusing a connection from uid {}",
+            uid
+        )));
+    }
+    Ok(())
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: getuid cannot fail and has no preconditions
+    unsafe { libc::getuid() }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> Result<u32, TotpError> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: `cred` and `length` describe a buffer of the size SO_PEERCRED writes
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(cred.uid)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> Result<u32, TotpError> {
+    let (mut uid, mut gid) = (0, 0);
+    // SAFETY: both pointers are to live locals
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(uid)
+}
+
+/// Vaults are identified by their absolute path, so relative `-s` paths match from any directory
+pub fn vault_id(sqlite_path: &str) -> String {
+    Path::new(sqlite_path)
+        .canonicalize()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| sqlite_path.to_string())
+}
@@ -0,0 +1,279 @@
+use crate::agent::{check_peer, check_socket_dir, Request, Response};
+use crate::db::kdf::Key;
+use crate::TotpError;
+use data_encoding::BASE64;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+/// Clients that stall are dropped so they cannot block everyone else
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct State {
+    keys: HashMap<String, Key>,
+    last_used: Instant,
+}
+
+impl State {
+    /// Dropping the keys zeroizes them
+    fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    /// Lock once nothing has asked for a key within `timeout`
+    fn expire(&mut self, timeout: Duration) -> bool {
+        if self.keys.is_empty() || self.last_used.elapsed() < timeout {
+            return false;
+        }
+        self.clear();
+        true
+    }
+}
+
+pub struct Agent {
+    socket: PathBuf,
+    timeout: Option<Duration>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Agent {
+    /// `timeout` is how long unlocked vaults stay available without use, `None` keeps them forever
+    pub fn new(socket: PathBuf, timeout: Option<Duration>) -> Self {
+        Self {
+            socket,
+            timeout,
+            state: Arc::new(Mutex::new(State {
+                keys: HashMap::new(),
+                last_used: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn start(&self) -> Result<(), TotpError> {
+        let listener = self.bind()?;
+        println!("Agent listening on {}", self.socket.display());
+
+        let state = self.state.clone();
+        let socket = self.socket.clone();
+        ctrlc::set_handler(move || {
+            if let Ok(mut state) = state.lock() {
+                state.clear();
+            }
+            let _ = std::fs::remove_file(&socket);
+            std::process::exit(0);
+        })
+        .map_err(|e| TotpError::Agent(e.to_string()))?;
+
+        if let Some(timeout) = self.timeout {
+            let state = self.state.clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(Duration::from_secs(1));
+                if state.lock().is_ok_and(|mut state| state.expire(timeout)) {
+                    log::info!("Locked after {}s of inactivity", timeout.as_secs());
+                }
+            });
+        }
+
+        for stream in listener.incoming() {
+            if let Err(e) = stream
+                .map_err(TotpError::from)
+                .and_then(|stream| self.handle(stream))
+            {
+                log::warn!("Agent request failed: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Bind the socket in a directory only the current user can enter
+    fn bind(&self) -> Result<UnixListener, TotpError> {
+        if let Some(dir) = self.socket.parent() {
+            if !dir.exists() {
+                std::fs::DirBuilder::new()
+                    .recursive(true)
+                    .mode(0o700)
+                    .create(dir)?;
+            }
+            check_socket_dir(dir)?;
+        }
+        if self.socket.exists() {
+            if UnixStream::connect(&self.socket).is_ok() {
+                return Err(TotpError::Agent(format!(
+                    "An agent is already listening on {}",
+                    self.socket.display()
+                )));
+            }
+            // Left behind by an agent that did not shut down cleanly
+            std::fs::remove_file(&self.socket)?;
+        }
+        let listener = UnixListener::bind(&self.socket)?;
+        std::fs::set_permissions(&self.socket, std::fs::Permissions::from_mode(0o600))?;
+        Ok(listener)
+    }
+
+    fn handle(&self, stream: UnixStream) -> Result<(), TotpError> {
+        check_peer(&stream)?;
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut line = Zeroizing::new(String::new());
+        // Connections that only check whether an agent is running send nothing
+        if BufReader::new(&stream).read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => self.respond(request),
+            Err(e) => Response::Error {
+                message: e.to_string(),
+            },
+        };
+        let response = Zeroizing::new(serde_json::to_string(&response)?);
+        (&stream).write_all(response.as_bytes())?;
+        (&stream).write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn respond(&self, request: Request) -> Response {
+        let Ok(mut state) = self.state.lock() else {
+            return Response::Error {
+                message: "Agent state is poisoned".to_string(),
+            };
+        };
+        match request {
+            Request::Unlock { vault, key } => {
+                let key = Zeroizing::new(key);
+                match BASE64
+                    .decode(key.as_bytes())
+                    .map_err(TotpError::from)
+                    .and_then(Key::from_bytes)
+                {
+                    Ok(key) => {
+                        state.keys.insert(vault, key);
+                        state.last_used = Instant::now();
+                        Response::Ok
+                    }
+                    Err(e) => Response::Error {
+                        message: format!("{:?}", e),
+                    },
+                }
+            }
+            Request::Lock { vault: Some(vault) } => {
+                state.keys.remove(&vault);
+                Response::Ok
+            }
+            Request::Lock { vault: None } => {
+                state.clear();
+                Response::Ok
+            }
+            Request::Key { vault } => match state.keys.get(&vault) {
+                Some(key) => {
+                    let key = BASE64.encode(key.as_bytes());
+                    state.last_used = Instant::now();
+                    Response::Key { key }
+                }
+                None => Response::Locked,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::Client;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn socket() -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+        std::env::temp_dir()
+            .join(format!("trotp-agent-test-{}", nanos))
+            .join("agent.sock")
+    }
+
+    #[test]
+    fn unlock_and_lock() {
+        let socket = socket();
+        let agent = Agent::new(socket.clone(), None);
+        let listener = agent.bind().unwrap();
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let mode = std::fs::metadata(socket.parent().unwrap())
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+        assert!(agent.bind().is_err());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                agent.handle(stream.unwrap()).unwrap();
+            }
+        });
+
+        let client = Client::new(socket.clone());
+        let key = Key::legacy("password");
+        assert!(client.key("vault").unwrap().is_none());
+        client.unlock("vault", &key).unwrap();
+        assert_eq!(client.key("vault").unwrap(), Some(key.clone()));
+        assert!(client.key("other").unwrap().is_none());
+        client.lock(Some("other")).unwrap();
+        assert_eq!(client.key("vault").unwrap(), Some(key));
+        client.lock(None).unwrap();
+        assert!(client.key("vault").unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(socket.parent().unwrap());
+    }
+
+    #[test]
+    fn refuse_unsafe_socket_dir() {
+        let socket = socket();
+        let dir = socket.parent().unwrap();
+        // Made by someone else before the agent started
+        std::fs::DirBuilder::new().mode(0o755).create(dir).unwrap();
+        let agent = Agent::new(socket.clone(), None);
+        assert!(matches!(agent.bind(), Err(TotpError::Agent(_))));
+        let client = Client::new(socket.clone());
+        assert!(matches!(client.key("vault"), Err(TotpError::Agent(_))));
+
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(agent.bind().is_ok());
+        // Only root can hand the directory to another user to check ownership
+        if unsafe { libc::getuid() } == 0 {
+            std::os::unix::fs::chown(dir, Some(65534), None).unwrap();
+            assert!(matches!(agent.bind(), Err(TotpError::Agent(_))));
+        }
+        let _ = std::fs::remove_dir_all(dir);
+
+        let (a, _b) = UnixStream::pair().unwrap();
+        assert!(crate::agent::check_peer(&a).is_ok());
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let agent = Agent::new(socket(), Some(Duration::from_secs(60)));
+        let unlock = || {
+            agent.respond(Request::Unlock {
+                vault: "vault".to_string(),
+                key: BASE64.encode(Key::legacy("password").as_bytes()),
+            })
+        };
+        let key = || {
+            agent.respond(Request::Key {
+                vault: "vault".to_string(),
+            })
+        };
+        assert!(matches!(unlock(), Response::Ok));
+        assert!(!agent.state.lock().unwrap().expire(Duration::from_secs(60)));
+        assert!(matches!(key(), Response::Key { .. }));
+        assert!(agent.state.lock().unwrap().expire(Duration::ZERO));
+        assert!(matches!(key(), Response::Locked));
+        // Nothing to lock
+        assert!(!agent.state.lock().unwrap().expire(Duration::ZERO));
+    }
+}
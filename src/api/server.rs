@@ -1,6 +1,6 @@
 use crate::db::models::record::Record;
 use crate::{Generator, StorageTrait, Token, TotpError};
-use serde_json::json;
+use serde_json::{json, Value};
 use std::net::SocketAddr;
 use std::str::FromStr;
 use tiny_http::{Response, Server as TinyServer};
@@ -24,7 +24,7 @@ where
         })
     }
 
-    pub fn start(&self) -> Result<(), TotpError> {
+    pub fn start(&mut self) -> Result<(), TotpError> {
         println!("Listening on {:?}", self.listen);
         ctrlc::set_handler(move || {
             std::process::exit(0);
@@ -32,37 +32,147 @@ where
         .expect("Error setting Ctrl-C handler");
 
         for request in self.server.incoming_requests() {
-            let account_or_secret = request.url().replace('/', "");
-            let decoded = urlencoding::decode(&account_or_secret)
-                .map_err(|e| TotpError::Utf8(e.to_string()))?;
-            let account_token_result = self.storage.search_account(&decoded).or_else(|_e| {
-                Token::from_str(&account_or_secret)
-                    .map(|token| ("Secret".to_string(), token))
-                    .map(|(account_name, token)| Record {
-                        account: Some(account_name),
-                        token: Some(token),
-                        ..Record::default()
-                    })
-            });
+            let result = respond(&mut self.storage, request.url());
+            let response = Response::from_string(result.to_string());
+            request.respond(response)?;
+        }
+        Ok(())
+    }
+}
+
+/// The JSON answer for a request path, which names an account like `code` does or carries a secret
+///
+/// Every failure becomes an error answer, so one bad request cannot stop the server.
+fn respond<T: StorageTrait>(storage: &mut T, url: &str) -> Value {
+    let account_or_secret = url.replace('/', "");
+    let decoded = match urlencoding::decode(&account_or_secret) {
+        Ok(decoded) => decoded,
+        Err(e) => return json!({"error": format!("Invalid path: {}", e)}),
+    };
+    let account_token_result = match storage.find_account(&decoded) {
+        Err(TotpError::AccountNotFound(account)) => Token::from_str(&account_or_secret)
+            .map(|token| Record {
+                account: Some("Secret".to_string()),
+                token: Some(token),
+                ..Record::default()
+            })
+            .map_err(|_| TotpError::AccountNotFound(account)),
+        result => result,
+    };
 
-            let result = if let Ok(record) = account_token_result {
-                if let Some(token) = record.token {
-                    if let Ok(generator) = Generator::new(token) {
-                        let (code, expiry) = generator.generate(None)?;
-                        json!({"account_name": record.account, "code": code, "expiry": expiry})
+    match account_token_result {
+        Ok(record) => {
+            if let Some(token) = record.token {
+                if let Ok(generator) = Generator::new(token) {
+                    if generator.is_hotp() {
+                        match storage.increment_counter(record.id) {
+                            Ok(counter) => {
+                                let code = generator.generate_hotp(counter);
+                                json!({"account_name": record.account, "code": code, "counter": counter})
+                            }
+                            Err(e) => {
+                                json!({"error": format!("Failed to advance the counter: {:?}", e)})
+                            }
+                        }
                     } else {
-                        json!({"error": "Failed to create generator"})
+                        match generator.generate(None) {
+                            Ok((code, expiry)) => {
+                                json!({"account_name": record.account, "code": code, "expiry": expiry})
+                            }
+                            Err(e) => {
+                                json!({"error": format!("Failed to generate a code: {:?}", e)})
+                            }
+                        }
                     }
                 } else {
-                    json!({"error": "Invalid token or account provided"})
+                    json!({"error": "Failed to create generator"})
                 }
             } else {
                 json!({"error": "Invalid token or account provided"})
-            };
+            }
+        }
+        Err(TotpError::AccountNotFound(account)) => {
+            json!({"error": format!("No account matches {}", account)})
+        }
+        Err(TotpError::AmbiguousAccount(matches)) => {
+            json!({"error": format!("More than one account matches: {}", matches)})
+        }
+        Err(e) => json!({"error": format!("{:?}", e)}),
+    }
+}
 
-            let response = Response::from_string(result.to_string());
-            request.respond(response)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::encryption::Encryption;
+    use crate::db::storage::sqlite::SqliteStorage;
+    use crate::db::Db;
+    use crate::otp::token::OtpType;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn storage() -> SqliteStorage {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+        let db = Db::new(
+            "password".to_string(),
+            Some(format!("file:apidb{}?mode=memory&cache=shared", nanos)),
+        )
+        .unwrap();
+        db.init().unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        for account in ["GitHub", "GitLab"] {
+            storage
+                .add_account(Record {
+                    account: Some(account.to_string()),
+                    token: Some(Token::from_str("JBSWY3DPEHPK3PXP").unwrap()),
+                    ..Record::default()
+                })
+                .unwrap();
         }
-        Ok(())
+        storage
+    }
+
+    #[test]
+    fn respond_only_to_a_single_account() {
+        let mut storage = storage();
+        let found = respond(&mut storage, "/GitHub");
+        assert_eq!(found["account_name"], "GitHub");
+        assert!(found["code"].is_string());
+
+        // A stray path or a partial name shared by several accounts is not guessed at
+        for path in ["/favicon.ico", "/Git", "/no-such-account"] {
+            let result = respond(&mut storage, path);
+            assert!(result["error"].is_string(), "{}", path);
+            assert!(result.get("code").is_none(), "{}", path);
+        }
+
+        let secret = respond(&mut storage, "/JBSWY3DPEHPK3PXP");
+        assert_eq!(secret["account_name"], "Secret");
+    }
+
+    #[test]
+    fn answer_storage_errors() {
+        let mut storage = storage();
+        let mut token = Token::from_str("JBSWY3DPEHPK3PXP").unwrap();
+        token.otp_type = OtpType::Hotp;
+        storage
+            .add_account(Record {
+                account: Some("Counter".to_string()),
+                token: Some(token),
+                ..Record::default()
+            })
+            .unwrap();
+
+        // Another writer, like the CLI re-keying the vault, holds the write lock
+        let writer = storage.db.pool.get().unwrap();
+        writer.execute_batch("BEGIN IMMEDIATE;").unwrap();
+        let busy = respond(&mut storage, "/Counter");
+        assert!(busy["error"].is_string(), "{}", busy);
+        writer.execute_batch("COMMIT;").unwrap();
+
+        let result = respond(&mut storage, "/Counter");
+        assert_eq!(result["counter"], 0);
     }
 }
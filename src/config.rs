@@ -0,0 +1,112 @@
+use crate::ui::clip::Backend;
+use crate::ui::keymap::{Keymap, KeymapConfig};
+use crate::TotpError;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Settings read from `config.toml`, every section is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub keymap: KeymapConfig,
+    pub tui: TuiConfig,
+    pub clipboard: ClipboardConfig,
+}
+
+/// The `[tui]` section
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TuiConfig {
+    /// Seconds without a key press before the TUI locks, 0 never locks
+    pub lock_after: u64,
+}
+
+impl Default for TuiConfig {
+    fn default() -> Self {
+        Self { lock_after: 300 }
+    }
+}
+
+/// The `[clipboard]` section
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClipboardConfig {
+    /// Seconds before a copied value is cleared again, 0 leaves it
+    pub clear_after: u64,
+    pub backend: Backend,
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            clear_after: 30,
+            backend: Backend::default(),
+        }
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/trotp/config.toml`, falling back to `~/.config` or `%APPDATA%`
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+        Some(dir.join("trotp").join("config.toml"))
+    }
+
+    /// Read `path`, or the default path when it exists, otherwise use the defaults
+    pub fn load(path: Option<&Path>) -> Result<Self, TotpError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path().filter(|path| path.exists()) {
+                Some(path) => path,
+                None => return Ok(Config::default()),
+            },
+        };
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| TotpError::Config(format!("{}: {}", path.display(), e)))?;
+        let config: Config = toml::from_str(&contents)
+            .map_err(|e| TotpError::Config(format!("{}: {}", path.display(), e)))?;
+        // Report clashing keys before the vault is unlocked
+        if let Err(TotpError::Config(e)) = Keymap::from_config(&config.keymap) {
+            return Err(TotpError::Config(format!("{}: {}", path.display(), e)));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::keymap::Preset;
+
+    #[test]
+    fn parse() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.keymap.preset, Preset::Default);
+        assert_eq!(config.tui.lock_after, 300);
+        assert_eq!(config.clipboard.clear_after, 30);
+        let config: Config = toml::from_str(
+            r#"
+            [keymap]
+            preset = "vim"
+            [keymap.bindings]
+            copy = ["enter", "y"]
+            quit = "Q"
+            [tui]
+            lock_after = 0
+            [clipboard]
+            backend = "clip.exe"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keymap.preset, Preset::Vim);
+        assert_eq!(config.keymap.bindings.len(), 2);
+        assert_eq!(config.tui.lock_after, 0);
+        assert_eq!(config.clipboard.backend, Backend::ClipExe);
+        assert!(toml::from_str::<Config>("[clipboard]\nbackend = \"xerox\"").is_err());
+        assert!(toml::from_str::<Config>("[keymap]\nunknown = 1").is_err());
+        assert!(Config::load(Some(Path::new("/nonexistent/config.toml"))).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use crate::db::encryption::Encryption;
+use crate::db::kdf::KdfParams;
+use crate::db::models::record::Record;
+use crate::TotpError;
+use chrono::{NaiveDateTime, Utc};
+use clap::ValueEnum;
+use data_encoding::BASE64;
+use serde::{Deserialize, Serialize};
+
+pub const FORMAT: &str = "trotp-backup";
+pub const VERSION: u32 = 2;
+const KDF_ALGORITHM: &str = "scrypt";
+
+/// A self-describing archive, only `data` is encrypted and it is keyed by its own password
+#[derive(Serialize, Deserialize)]
+pub struct Backup {
+    pub format: String,
+    pub version: u32,
+    pub created_at: NaiveDateTime,
+    kdf: KdfHeader,
+    /// The sealed `Payload`
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KdfHeader {
+    algorithm: String,
+    salt: String,
+    log_n: u8,
+    r: u32,
+    p: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Payload {
+    app_version: String,
+    records: Vec<Record>,
+}
+
+/// What to do with a backed up account whose name matches an account with another secret
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Conflict {
+    /// Keep the existing account
+    #[default]
+    Skip,
+    /// Replace the existing account with the backed up one
+    Overwrite,
+    /// Add the backed up account alongside the existing one
+    KeepBoth,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// A new account, with an id of 0
+    Added,
+    /// An existing account changed, keeping its id
+    Updated,
+    /// The account already exists with the same secret
+    Unchanged,
+    /// A conflicting account was kept
+    Skipped,
+}
+
+impl KdfHeader {
+    fn params(&self) -> Result<KdfParams, TotpError> {
+        if self.algorithm != KDF_ALGORITHM {
+            return Err(TotpError::Backup(format!(
+                "Unsupported key derivation {}",
+                self.algorithm
+            )));
+        }
+        let params = KdfParams {
+            salt: BASE64.decode(self.salt.as_bytes())?,
+            log_n: self.log_n,
+            r: self.r,
+            p: self.p,
+            keyfile: false,
+            sealed: true,
+        };
+        if params.max_memory().is_none() {
+            return Err(TotpError::Backup(format!(
+                "Unsupported scrypt parameters log_n {}, r {}, p {}",
+                self.log_n, self.r, self.p
+            )));
+        }
+        Ok(params)
+    }
+}
+
+impl Backup {
+    pub fn seal(records: &[Record], password: &str) -> Result<Self, TotpError> {
+        let params = KdfParams::generate()?;
+        let key = params.derive(password, None)?;
+        let payload = serde_json::to_string(&Payload {
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            records: records.to_vec(),
+        })?;
+        let kdf = KdfHeader {
+            algorithm: KDF_ALGORITHM.to_string(),
+            salt: BASE64.encode(&params.salt),
+            log_n: params.log_n,
+            r: params.r,
+            p: params.p,
+        };
+        let data = Encryption::default().seal(&payload, &key, &associated_data(VERSION, &kdf))?;
+        Ok(Self {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            created_at: Utc::now().naive_utc(),
+            kdf,
+            data,
+        })
+    }
+
+    pub fn open(&self, password: &str) -> Result<Vec<Record>, TotpError> {
+        if self.format != FORMAT {
+            return Err(TotpError::Backup("Not a trotp backup".to_string()));
+        }
+        if self.version > VERSION {
+            return Err(TotpError::Backup(format!(
+                "Backup version {} needs a newer trotp",
+                self.version
+            )));
+        }
+        let key = self.kdf.params()?.derive(password, None)?;
+        let payload = Encryption::default()
+            .open(&self.data, &key, &associated_data(self.version, &self.kdf))
+            .map_err(|_| TotpError::Backup("Invalid backup password".to_string()))?;
+        Ok(serde_json::from_str::<Payload>(&payload)?.records)
+    }
+}
+
+/// Binds the payload to the format version and, since version 2, the key derivation header
+fn associated_data(version: u32, kdf: &KdfHeader) -> String {
+    if version < 2 {
+        return format!("{}:{}", FORMAT, version);
+    }
+    format!(
+        "{}:{}:{}:{}:{}:{}:{}",
+        FORMAT, version, kdf.algorithm, kdf.salt, kdf.log_n, kdf.r, kdf.p
+    )
+}
+
+/// Decide how each backed up record merges into the existing ones
+///
+/// A record whose secret already exists is unchanged, apart from never lowering an HOTP counter.
+/// A record with the name and user of an account with another secret is resolved by `conflict`.
+pub fn merge(
+    existing: &[Record],
+    backup: Vec<Record>,
+    conflict: Conflict,
+) -> Vec<(Outcome, Record)> {
+    let secret = |record: &Record| record.token.as_ref().map(|token| token.secret.clone());
+    let name = |record: &Record| {
+        (
+            record.account.clone().unwrap_or_default().to_lowercase(),
+            record.user.clone().unwrap_or_default().to_lowercase(),
+        )
+    };
+    backup
+        .into_iter()
+        .map(|record| {
+            if let Some(current) = existing
+                .iter()
+                .find(|current| secret(current).is_some() && secret(current) == secret(&record))
+            {
+                return if record.counter > current.counter {
+                    (
+                        Outcome::Updated,
+                        Record {
+                            counter: record.counter,
+                            ..current.clone()
+                        },
+                    )
+                } else {
+                    (Outcome::Unchanged, record)
+                };
+            }
+            match existing
+                .iter()
+                .find(|current| name(current) == name(&record))
+            {
+                None => (Outcome::Added, Record { id: 0, ..record }),
+                Some(_) if conflict == Conflict::Skip => (Outcome::Skipped, record),
+                Some(_) if conflict == Conflict::KeepBoth => {
+                    (Outcome::Added, Record { id: 0, ..record })
+                }
+                Some(current) => (
+                    Outcome::Updated,
+                    Record {
+                        id: current.id,
+                        created_at: current.created_at,
+                        ..record
+                    },
+                ),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Token;
+    use std::str::FromStr;
+
+    fn record(id: u32, account: &str, secret: &str, counter: u64) -> Record {
+        Record {
+            id,
+            account: Some(account.to_string()),
+            token: Some(Token::from_str(secret).unwrap()),
+            counter,
+            ..Record::default()
+        }
+    }
+
+    #[test]
+    fn seal_open() {
+        let records = vec![
+            record(1, "GitHub", "JBSWY3DPEHPK3PXP", 0),
+            record(2, "Vpn", "KRSXG5A=", 7),
+        ];
+        let backup = Backup::seal(&records, "backup password").unwrap();
+        let json = serde_json::to_string(&backup).unwrap();
+        assert!(!json.contains("GitHub"));
+
+        let backup: Backup = serde_json::from_str(&json).unwrap();
+        assert_eq!(backup.format, FORMAT);
+        assert_eq!(backup.version, VERSION);
+        assert!(matches!(backup.open("wrong"), Err(TotpError::Backup(_))));
+        let restored = backup.open("backup password").unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored[1].account, Some("Vpn".to_string()));
+        assert_eq!(restored[1].counter, 7);
+        assert_eq!(restored[1].token, records[1].token);
+
+        let future = Backup {
+            version: VERSION + 1,
+            ..serde_json::from_str(&json).unwrap()
+        };
+        assert!(future.open("backup password").is_err());
+
+        // The header is authenticated along with the payload
+        let mut tampered: Backup = serde_json::from_str(&json).unwrap();
+        tampered.kdf.log_n -= 1;
+        assert!(matches!(
+            tampered.open("backup password"),
+            Err(TotpError::Backup(_))
+        ));
+        for (log_n, r, p) in [(64, 8, 1), (15, u32::MAX, u32::MAX)] {
+            let mut hostile: Backup = serde_json::from_str(&json).unwrap();
+            hostile.kdf = KdfHeader {
+                log_n,
+                r,
+                p,
+                ..hostile.kdf
+            };
+            assert!(matches!(
+                hostile.open("backup password"),
+                Err(TotpError::Backup(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn open_version_1() {
+        let records = vec![record(1, "GitHub", "JBSWY3DPEHPK3PXP", 0)];
+        let mut backup = Backup::seal(&records, "backup password").unwrap();
+        let key = backup
+            .kdf
+            .params()
+            .unwrap()
+            .derive("backup password", None)
+            .unwrap();
+        let payload = serde_json::to_string(&Payload {
+            app_version: "0.1.0".to_string(),
+            records,
+        })
+        .unwrap();
+        backup.version = 1;
+        backup.data = Encryption::default()
+            .seal(&payload, &key, &associated_data(1, &backup.kdf))
+            .unwrap();
+        assert_eq!(backup.open("backup password").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn merge_records() {
+        let existing = vec![
+            record(1, "GitHub", "JBSWY3DPEHPK3PXP", 0),
+            record(2, "Vpn", "KRSXG5A=", 3),
+            record(3, "Bank", "GEZDGNBV", 0),
+        ];
+        let backup = || {
+            vec![
+                record(10, "GitHub (old name)", "JBSWY3DPEHPK3PXP", 0),
+                record(11, "Vpn", "KRSXG5A=", 9),
+                record(12, "bank", "MFRGGZDF", 0),
+                record(13, "Mail", "ONSWG4TF", 0),
+            ]
+        };
+        let outcomes = |conflict| {
+            merge(&existing, backup(), conflict)
+                .into_iter()
+                .map(|(outcome, record)| (outcome, record.id, record.counter))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            outcomes(Conflict::Skip),
+            vec![
+                (Outcome::Unchanged, 10, 0),
+                (Outcome::Updated, 2, 9),
+                (Outcome::Skipped, 12, 0),
+                (Outcome::Added, 0, 0),
+            ]
+        );
+        assert_eq!(outcomes(Conflict::Overwrite)[2], (Outcome::Updated, 3, 0));
+        assert_eq!(outcomes(Conflict::KeepBoth)[2], (Outcome::Added, 0, 0));
+    }
+}
@@ -1,7 +1,13 @@
+use crate::db::kdf::Key;
 use crate::TotpError;
 use data_encoding::BASE64;
 use openssl::rand::rand_bytes;
-use openssl::symm::{decrypt, encrypt, Cipher};
+use openssl::symm::{decrypt, decrypt_aead, encrypt, encrypt_aead, Cipher};
+
+/// Prefix of fields sealed with AES-256-GCM, older fields are stored as CBC `content:iv`
+const SEALED_PREFIX: &str = "v2:";
+const NONCE_LENGTH: usize = 12;
+const TAG_LENGTH: usize = 16;
 
 pub struct Encryption {
     pub key: String,
@@ -17,16 +23,83 @@ impl Default for Encryption {
 }
 
 impl Encryption {
-    pub fn encrypt(&self, content: &str, password: &str) -> Result<(String, String), TotpError> {
-        let cipher = Cipher::aes_256_cbc();
-        let mut password = password.as_bytes().to_vec();
-        while password.len() < cipher.key_len() {
-            password.push(b'0');
+    /// Encrypt a field as `v2:nonce:ciphertext` with AES-256-GCM
+    ///
+    /// `associated_data` is authenticated but not stored, so a field only opens in the place it
+    /// was sealed for.
+    pub fn seal(
+        &self,
+        content: &str,
+        key: &Key,
+        associated_data: &str,
+    ) -> Result<String, TotpError> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand_bytes(&mut nonce)?;
+        let mut tag = [0u8; TAG_LENGTH];
+        let mut encrypted_content = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            key.as_bytes(),
+            Some(&nonce),
+            associated_data.as_bytes(),
+            content.as_bytes(),
+            &mut tag,
+        )?;
+        encrypted_content.extend_from_slice(&tag);
+        Ok(format!(
+            "{}{}:{}",
+            SEALED_PREFIX,
+            BASE64.encode(&nonce),
+            BASE64.encode(&encrypted_content)
+        ))
+    }
+
+    /// Decrypt a field written by `seal`, or by `encrypt` in the legacy `content:iv` format
+    pub fn open(&self, value: &str, key: &Key, associated_data: &str) -> Result<String, TotpError> {
+        let Some(sealed) = value.strip_prefix(SEALED_PREFIX) else {
+            return match value.split_once(':') {
+                Some((content, iv)) if !content.is_empty() && !iv.is_empty() => {
+                    self.decrypt(content, key, iv)
+                }
+                _ => Err(TotpError::Decryption(
+                    "Malformed encrypted field".to_string(),
+                )),
+            };
+        };
+        let (nonce, content) = sealed
+            .split_once(':')
+            .ok_or_else(|| TotpError::Decryption("Malformed encrypted field".to_string()))?;
+        let nonce = BASE64.decode(nonce.as_bytes())?;
+        let content = BASE64.decode(content.as_bytes())?;
+        if content.len() < TAG_LENGTH {
+            return Err(TotpError::Decryption(
+                "Malformed encrypted field".to_string(),
+            ));
         }
+        let (data, tag) = content.split_at(content.len() - TAG_LENGTH);
+        let decrypted_content = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            key.as_bytes(),
+            Some(&nonce),
+            associated_data.as_bytes(),
+            data,
+            tag,
+        )
+        .map_err(|_e| TotpError::Decryption("Invalid password or tampered data".to_string()))?;
+        String::from_utf8(decrypted_content).map_err(|e| TotpError::Utf8(e.to_string()))
+    }
+
+    /// Whether a stored field already uses authenticated encryption
+    pub fn is_sealed(value: &str) -> bool {
+        value.starts_with(SEALED_PREFIX)
+    }
+
+    /// Legacy AES-256-CBC encryption, kept so fields can be written in the old format for tests
+    pub fn encrypt(&self, content: &str, key: &Key) -> Result<(String, String), TotpError> {
+        let cipher = Cipher::aes_256_cbc();
         let content =
             content.trim_end().to_owned() + format!("\n{}:{}", self.key, self.value).as_str();
         let data = content.as_bytes();
-        let key = password.as_slice();
+        let key = key.as_bytes();
 
         let iv = {
             let mut buf = vec![0; cipher.iv_len().unwrap_or(0)];
@@ -40,16 +113,13 @@ impl Encryption {
         ))
     }
 
-    pub fn decrypt(&self, content: &str, password: &str, iv: &str) -> Result<String, TotpError> {
+    /// Legacy AES-256-CBC decryption, relies on the sentinel to notice a wrong key
+    pub fn decrypt(&self, content: &str, key: &Key, iv: &str) -> Result<String, TotpError> {
         let base64_decoded_content = BASE64.decode(content.as_bytes())?;
         let iv_decoded = BASE64.decode(iv.as_bytes())?;
         let cipher = Cipher::aes_256_cbc();
-        let mut password = password.as_bytes().to_vec();
-        while password.len() < cipher.key_len() {
-            password.push(b'0');
-        }
         let data = base64_decoded_content.as_slice();
-        let key = password.as_slice();
+        let key = key.as_bytes();
         let decrypted_content = decrypt(cipher, key, Some(iv_decoded.as_slice()), data)
             .map_err(|_e| TotpError::Decryption("Invalid password".to_string()))?;
         let decrypted_content =
@@ -70,7 +140,9 @@ mod tests {
     #[test]
     fn encrypt() {
         let encrypt = Encryption::default();
-        let (content, _iv) = encrypt.encrypt("TestContent", "password").unwrap();
+        let (content, _iv) = encrypt
+            .encrypt("TestContent", &Key::legacy("password"))
+            .unwrap();
         assert!(!content.contains("TestContent"));
     }
 
@@ -80,7 +152,7 @@ mod tests {
         let content = encrypt
             .decrypt(
                 "3wm4AUCJG+/Cr+NiZ/6M1tRaJp8ivdJCIFbzI8CcsTE=",
-                "password",
+                &Key::legacy("password"),
                 "ow1G5PUj8YY3Avnq2QpOPQ==",
             )
             .unwrap();
@@ -92,7 +164,7 @@ mod tests {
         let encrypt = Encryption::default();
         let content = encrypt.decrypt(
             "3wm4AUCJG+/Cr+NiZ/6M1tRaJp8ivdJCIFbzI8CcsTE=",
-            "wrong",
+            &Key::legacy("wrong"),
             "ow1G5PUj8YY3Avnq2QpOPQ==",
         );
         assert!(content.is_err());
@@ -101,9 +173,74 @@ mod tests {
     #[test]
     fn encrypt_decrypt() {
         let encrypt = Encryption::default();
-        let (content, iv) = encrypt.encrypt("TestContent", "password").unwrap();
+        let (content, iv) = encrypt
+            .encrypt("TestContent", &Key::legacy("password"))
+            .unwrap();
         assert!(!content.contains("TestContent"));
-        let content = encrypt.decrypt(&content, "password", &iv).unwrap();
+        let content = encrypt
+            .decrypt(&content, &Key::legacy("password"), &iv)
+            .unwrap();
         assert_eq!(content, "TestContent");
     }
+
+    #[test]
+    fn seal_open() {
+        let encryption = Encryption::default();
+        let key = Key::legacy("password");
+        let sealed = encryption
+            .seal("TestContent", &key, "secure_records:1:token")
+            .unwrap();
+        assert!(Encryption::is_sealed(&sealed));
+        assert!(!sealed.contains("TestContent"));
+        assert_eq!(
+            encryption
+                .open(&sealed, &key, "secure_records:1:token")
+                .unwrap(),
+            "TestContent"
+        );
+        // Fields cannot be moved to another row or column, or opened with another key
+        assert!(encryption
+            .open(&sealed, &key, "secure_records:2:token")
+            .is_err());
+        assert!(encryption
+            .open(&sealed, &key, "secure_records:1:note")
+            .is_err());
+        assert!(encryption
+            .open(&sealed, &Key::legacy("wrong"), "secure_records:1:token")
+            .is_err());
+    }
+
+    #[test]
+    fn open_tampered() {
+        let encryption = Encryption::default();
+        let key = Key::legacy("password");
+        let sealed = encryption.seal("TestContent", &key, "").unwrap();
+        let (prefix, content) = sealed.rsplit_once(':').unwrap();
+        let mut content = BASE64.decode(content.as_bytes()).unwrap();
+        content[0] ^= 1;
+        let tampered = format!("{}:{}", prefix, BASE64.encode(&content));
+        assert!(encryption.open(&tampered, &key, "").is_err());
+    }
+
+    #[test]
+    fn open_legacy() {
+        let encryption = Encryption::default();
+        let content = encryption
+            .open(
+                "3wm4AUCJG+/Cr+NiZ/6M1tRaJp8ivdJCIFbzI8CcsTE=:ow1G5PUj8YY3Avnq2QpOPQ==",
+                &Key::legacy("password"),
+                "secure_records:1:token",
+            )
+            .unwrap();
+        assert_eq!(content, "TestContent");
+        assert!(!Encryption::is_sealed(
+            "3wm4AUCJG+/Cr+NiZ/6M1tRaJp8ivdJCIFbzI8CcsTE=:ow1G5PUj8YY3Avnq2QpOPQ=="
+        ));
+        for malformed in ["", "content", ":iv", "content:", "v2:nonce"] {
+            assert!(matches!(
+                encryption.open(malformed, &Key::legacy("password"), ""),
+                Err(TotpError::Decryption(_))
+            ));
+        }
+    }
 }
@@ -0,0 +1,332 @@
+use crate::db::Connection;
+use crate::TotpError;
+use data_encoding::BASE64;
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::scrypt;
+use openssl::pkey::PKey;
+use openssl::rand::rand_bytes;
+use openssl::sha::sha256;
+use openssl::sign::Signer;
+use r2d2_sqlite::rusqlite::{params, OptionalExtension};
+use std::fmt::{Debug, Formatter};
+use std::path::Path;
+use zeroize::{Zeroize, Zeroizing};
+
+pub const KEY_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+const ALGORITHM: &str = "scrypt";
+/// The largest scrypt cost accepted from a vault or a file, 2^20 rounds of 8 blocks uses 1GiB
+const MAX_LOG_N: u32 = 20;
+const MAX_RP: u64 = 1 << 30;
+
+/// The memory OpenSSL must be allowed for scrypt, `None` when the parameters are out of bounds
+///
+/// OpenSSL refuses to use more than 32MiB unless told otherwise, so this leaves that headroom.
+pub fn scrypt_max_memory(n: u64, r: u64, p: u64) -> Option<u64> {
+    if n < 2 || !n.is_power_of_two() || n > 1 << MAX_LOG_N || r == 0 || p == 0 {
+        return None;
+    }
+    if r.checked_mul(p)? >= MAX_RP {
+        return None;
+    }
+    128u64
+        .checked_mul(n)?
+        .checked_mul(r)?
+        .checked_mul(p)?
+        .checked_add(32 * 1024 * 1024)
+}
+
+/// A symmetric key used to encrypt record fields
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key(Vec<u8>);
+
+impl Key {
+    /// The key vaults created before key derivation used, the password padded with '0'
+    pub fn legacy(password: &str) -> Self {
+        let mut key = password.as_bytes().to_vec();
+        while key.len() < KEY_LENGTH {
+            key.push(b'0');
+        }
+        Self(key)
+    }
+
+    /// A key handed over by the agent, which must have been derived for a vault
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, TotpError> {
+        if bytes.len() < KEY_LENGTH {
+            return Err(TotpError::Decryption("Invalid key length".to_string()));
+        }
+        Ok(Self(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Key(..)")
+    }
+}
+
+/// The contents of a file required alongside the password to unlock the vault
+#[derive(Clone)]
+pub struct Keyfile(Vec<u8>);
+
+impl Keyfile {
+    pub fn read(path: &Path) -> Result<Self, TotpError> {
+        let contents = std::fs::read(path).map_err(|e| {
+            TotpError::Password(format!("Cannot read keyfile {}: {}", path.display(), e))
+        })?;
+        if contents.is_empty() {
+            return Err(TotpError::Password(format!(
+                "Keyfile {} is empty",
+                path.display()
+            )));
+        }
+        Ok(Self(contents))
+    }
+
+    /// A new keyfile of random bytes
+    pub fn generate() -> Result<Self, TotpError> {
+        let mut contents = vec![0u8; 64];
+        rand_bytes(contents.as_mut_slice())?;
+        Ok(Self(contents))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+impl Drop for Keyfile {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Debug for Keyfile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Keyfile(..)")
+    }
+}
+
+/// The scrypt parameters and salt of a vault, stored in the `kdf` table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KdfParams {
+    pub salt: Vec<u8>,
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    /// Whether the key also depends on a keyfile, so its absence can be reported clearly
+    pub keyfile: bool,
+    /// Whether every field has been sealed, after which legacy fields are rejected as tampered
+    ///
+    /// The lock is sealed with this flag in its associated data, so clearing it is noticed.
+    pub sealed: bool,
+}
+
+impl KdfParams {
+    /// Fresh parameters with a random salt, 2^15 rounds of 8 blocks uses 32MiB
+    pub fn generate() -> Result<Self, TotpError> {
+        let mut salt = vec![0u8; SALT_LENGTH];
+        rand_bytes(salt.as_mut_slice())?;
+        Ok(Self {
+            salt,
+            log_n: 15,
+            r: 8,
+            p: 1,
+            keyfile: false,
+            sealed: true,
+        })
+    }
+
+    /// See `scrypt_max_memory`
+    pub fn max_memory(&self) -> Option<u64> {
+        let n = 1u64.checked_shl(self.log_n as u32)?;
+        scrypt_max_memory(n, self.r as u64, self.p as u64)
+    }
+
+    /// Derive the key from the password, mixed with the keyfile when there is one
+    ///
+    /// The keyfile's SHA-256 keys an HMAC of the password, so the two cannot run into each other
+    /// the way a plain concatenation would.
+    pub fn derive(&self, password: &str, keyfile: Option<&Keyfile>) -> Result<Key, TotpError> {
+        let secret = match keyfile {
+            Some(keyfile) => {
+                let digest = Zeroizing::new(sha256(keyfile.as_bytes()));
+                let hmac_key = PKey::hmac(digest.as_slice())?;
+                let mut signer = Signer::new(MessageDigest::sha256(), &hmac_key)?;
+                signer.update(password.as_bytes())?;
+                Zeroizing::new(signer.sign_to_vec()?)
+            }
+            None => Zeroizing::new(password.as_bytes().to_vec()),
+        };
+        let max_memory = self.max_memory().ok_or_else(|| {
+            TotpError::Decryption("Unsupported key derivation parameters".to_string())
+        })?;
+        let mut key = vec![0u8; KEY_LENGTH];
+        scrypt(
+            &secret,
+            &self.salt,
+            1 << self.log_n,
+            self.r as u64,
+            self.p as u64,
+            max_memory,
+            key.as_mut_slice(),
+        )?;
+        Ok(Key(key))
+    }
+
+    pub fn load(connection: &Connection) -> Result<Option<Self>, TotpError> {
+        const SQL: &str = "SELECT algorithm, salt, log_n, r, p, keyfile, sealed FROM kdf LIMIT 1;";
+        let mut stmt = connection.prepare(SQL)?;
+        let row = stmt
+            .query_row(params![], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, u8>(2)?,
+                    row.get::<_, u32>(3)?,
+                    row.get::<_, u32>(4)?,
+                    row.get::<_, bool>(5)?,
+                    row.get::<_, bool>(6)?,
+                ))
+            })
+            .optional()?;
+        match row {
+            Some((algorithm, salt, log_n, r, p, keyfile, sealed)) if algorithm == ALGORITHM => {
+                Ok(Some(Self {
+                    salt: BASE64.decode(salt.as_bytes())?,
+                    log_n,
+                    r,
+                    p,
+                    keyfile,
+                    sealed,
+                }))
+            }
+            Some((algorithm, ..)) => Err(TotpError::Decryption(format!(
+                "Unsupported key derivation {}",
+                algorithm
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save(&self, connection: &Connection) -> Result<(), TotpError> {
+        connection.prepare("DELETE FROM kdf;")?.execute(params![])?;
+        const SQL: &str = "INSERT INTO kdf (algorithm, salt, log_n, r, p, keyfile, sealed) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);";
+        connection.prepare(SQL)?.execute(params![
+            ALGORITHM,
+            BASE64.encode(&self.salt),
+            self.log_n,
+            self.r,
+            self.p,
+            self.keyfile,
+            self.sealed
+        ])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derive() {
+        let params = KdfParams {
+            salt: b"salt".to_vec(),
+            log_n: 10,
+            r: 8,
+            p: 1,
+            keyfile: false,
+            sealed: true,
+        };
+        let key = params.derive("password", None).unwrap();
+        assert_eq!(key.as_bytes().len(), KEY_LENGTH);
+        assert_eq!(key, params.derive("password", None).unwrap());
+        assert_ne!(key, params.derive("Password", None).unwrap());
+        let other_salt = KdfParams {
+            salt: b"pepper".to_vec(),
+            ..params.clone()
+        };
+        assert_ne!(key, other_salt.derive("password", None).unwrap());
+        // Long passwords no longer overflow the key
+        let long = params.derive(&"a".repeat(100), None).unwrap();
+        assert_eq!(long.as_bytes().len(), KEY_LENGTH);
+    }
+
+    #[test]
+    fn reject_unbounded_params() {
+        let params = KdfParams {
+            salt: b"salt".to_vec(),
+            log_n: 10,
+            r: 8,
+            p: 1,
+            keyfile: false,
+            sealed: true,
+        };
+        for params in [
+            KdfParams {
+                log_n: 64,
+                ..params.clone()
+            },
+            KdfParams {
+                log_n: 21,
+                ..params.clone()
+            },
+            KdfParams {
+                r: u32::MAX,
+                p: u32::MAX,
+                ..params.clone()
+            },
+            KdfParams {
+                p: 0,
+                ..params.clone()
+            },
+        ] {
+            assert!(params.max_memory().is_none());
+            assert!(params.derive("password", None).is_err());
+        }
+        assert_eq!(scrypt_max_memory(1 << 15, 8, 1), Some(64 * 1024 * 1024));
+        assert_eq!(scrypt_max_memory(1000, 8, 1), None);
+    }
+
+    #[test]
+    fn derive_keyfile() {
+        let params = KdfParams {
+            salt: b"salt".to_vec(),
+            log_n: 10,
+            r: 8,
+            p: 1,
+            keyfile: true,
+            sealed: true,
+        };
+        let keyfile = Keyfile(b"keyfile".to_vec());
+        let key = params.derive("password", Some(&keyfile)).unwrap();
+        assert_eq!(key, params.derive("password", Some(&keyfile)).unwrap());
+        assert_ne!(key, params.derive("password", None).unwrap());
+        let other = Keyfile(b"other".to_vec());
+        assert_ne!(key, params.derive("password", Some(&other)).unwrap());
+        // HMAC-SHA256 keyed with the keyfile digest, then scrypt, pinned so vaults keep opening
+        assert_eq!(
+            BASE64.encode(key.as_bytes()),
+            "Fat8SuBzBFC96zPTklRxfSvN2c8ah5pcClfqYdwle6s="
+        );
+    }
+
+    #[test]
+    fn legacy_key() {
+        assert_eq!(
+            Key::legacy("password").as_bytes(),
+            b"password000000000000000000000000"
+        );
+    }
+}
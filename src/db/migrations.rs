@@ -24,6 +24,32 @@ pub fn migrations() -> Migrations<'static> {
             );
             "#,
         ),
+        M::up(
+            r#"
+            ALTER TABLE secure_records ADD COLUMN counter INTEGER NOT NULL DEFAULT 0;
+            "#,
+        ),
+        M::up(
+            r#"
+            CREATE TABLE IF NOT EXISTS kdf (
+                algorithm TEXT NOT NULL,
+                salt TEXT NOT NULL,
+                log_n INTEGER NOT NULL,
+                r INTEGER NOT NULL,
+                p INTEGER NOT NULL
+            );
+            "#,
+        ),
+        M::up(
+            r#"
+            ALTER TABLE kdf ADD COLUMN keyfile INTEGER NOT NULL DEFAULT 0;
+            "#,
+        ),
+        M::up(
+            r#"
+            ALTER TABLE kdf ADD COLUMN sealed INTEGER NOT NULL DEFAULT 0;
+            "#,
+        ),
     ])
 }
 
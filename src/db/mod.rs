@@ -1,21 +1,28 @@
 #![allow(dead_code)]
 #![allow(clippy::large_enum_variant)]
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
+use crate::db::kdf::{KdfParams, Key, Keyfile};
 use crate::TotpError;
 use r2d2::{Pool, PooledConnection};
-use r2d2_sqlite::rusqlite::{Statement, Transaction};
+use r2d2_sqlite::rusqlite::{params, Statement, Transaction};
 use r2d2_sqlite::SqliteConnectionManager;
+use zeroize::Zeroize;
 
+pub mod backup;
 pub mod encryption;
+pub mod kdf;
 mod migrations;
 pub mod models;
+pub mod search;
 pub mod storage;
 
 #[derive(Debug, Clone)]
 pub struct Db {
     pub pool: Arc<Pool<SqliteConnectionManager>>,
     password: String,
+    keyfile: Option<Keyfile>,
+    key: OnceLock<Key>,
 }
 
 impl Db {
@@ -28,16 +35,88 @@ impl Db {
 
         let sqlite_pool = Pool::new(sqlite_connection_manager)?;
         let pool = Arc::new(sqlite_pool);
-        Ok(Db { pool, password })
+        Ok(Db {
+            pool,
+            password,
+            keyfile: None,
+            key: OnceLock::new(),
+        })
     }
 
     pub fn init(&self) -> Result<(), TotpError> {
         let mut connection = self.pool.get()?;
         let migrations = migrations::migrations();
         migrations.to_latest(&mut connection)?;
+        // New vaults start out with a derived key, existing ones are upgraded once unlocked
+        let connection = Connection::Pooled(connection);
+        if KdfParams::load(&connection)?.is_none() && Self::is_empty(&connection)? {
+            KdfParams::generate()?.save(&connection)?;
+        }
         Ok(())
     }
 
+    fn is_empty(connection: &Connection) -> Result<bool, TotpError> {
+        const SQL: &str =
+            "SELECT (SELECT COUNT(*) FROM secure_records) + (SELECT COUNT(*) FROM table_lock);";
+        let mut stmt = connection.prepare(SQL)?;
+        let rows = stmt.query_row(params![], |row| row.get::<_, i64>(0))?;
+        Ok(rows == 0)
+    }
+
+    /// The key record fields are encrypted with, derived from the password on first use
+    pub fn key(&self) -> Result<&Key, TotpError> {
+        if let Some(key) = self.key.get() {
+            return Ok(key);
+        }
+        let key = match (
+            KdfParams::load(&Connection::try_from(self)?)?,
+            &self.keyfile,
+        ) {
+            (Some(params), None) if params.keyfile => {
+                return Err(TotpError::Password(
+                    "This vault requires a keyfile, pass --keyfile".to_string(),
+                ));
+            }
+            (Some(params), keyfile) if params.keyfile == keyfile.is_some() => {
+                params.derive(&self.password, keyfile.as_ref())?
+            }
+            (None, None) => Key::legacy(&self.password),
+            _ => {
+                return Err(TotpError::Password(
+                    "This vault does not use a keyfile".to_string(),
+                ));
+            }
+        };
+        Ok(self.key.get_or_init(|| key))
+    }
+
+    pub fn set_key(&mut self, key: Key) {
+        self.key = OnceLock::from(key);
+    }
+
+    /// Whether the vault still uses the zero padded password as its key
+    pub fn is_legacy(&self) -> Result<bool, TotpError> {
+        Ok(KdfParams::load(&Connection::try_from(self)?)?.is_none())
+    }
+
+    /// Drop the password and derived key, anything needing the key fails until a password is set
+    pub fn forget(&mut self) {
+        self.password.zeroize();
+        self.key = OnceLock::new();
+    }
+
+    pub fn set_password(&mut self, password: String) {
+        self.password = password;
+    }
+
+    pub fn set_keyfile(&mut self, keyfile: Option<Keyfile>) {
+        self.keyfile = keyfile;
+    }
+
+    pub fn keyfile(&self) -> Option<&Keyfile> {
+        self.keyfile.as_ref()
+    }
+
     pub fn password(&self) -> &str {
         self.password.as_str()
     }
@@ -64,7 +143,7 @@ impl<'a> From<Transaction<'a>> for Connection<'a> {
 
 impl<'a> Connection<'a> {
     #[inline]
-    pub fn prepare(&self, query: &str) -> Result<Statement, TotpError> {
+    pub fn prepare(&self, query: &str) -> Result<Statement<'_>, TotpError> {
         match self {
             Connection::Pooled(client) => Ok(client.prepare(query)?),
             Connection::Transaction(transaction) => Ok(transaction.prepare(query)?),
@@ -1,4 +1,5 @@
 use crate::db::encryption::Encryption;
+use crate::db::kdf::Key;
 use crate::db::models::secure_record::SecureRecord;
 use crate::{Token, TotpError};
 use chrono::{NaiveDateTime, Utc};
@@ -18,6 +19,9 @@ pub struct Record {
     pub note: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    /// The next HOTP counter value, unused for TOTP accounts
+    #[serde(default)]
+    pub counter: u64,
 }
 
 impl Display for Record {
@@ -44,89 +48,176 @@ impl Default for Record {
             note: None,
             created_at: Utc::now().naive_utc(),
             updated_at: Utc::now().naive_utc(),
+            counter: 0,
         }
     }
 }
 
 impl Record {
+    /// One `field: old -> new` line per changed field, the secret and password are never shown
+    pub fn diff(&self, other: &Record) -> Vec<String> {
+        let text = |value: Option<String>| value.unwrap_or_else(|| "(none)".to_string());
+        let token =
+            |record: &Record, field: fn(&Token) -> String| text(record.token.as_ref().map(field));
+        let fields = [
+            (
+                "account",
+                text(self.account.clone()),
+                text(other.account.clone()),
+            ),
+            ("user", text(self.user.clone()), text(other.user.clone())),
+            ("note", text(self.note.clone()), text(other.note.clone())),
+            (
+                "algorithm",
+                token(self, |t| t.algorithm.to_string()),
+                token(other, |t| t.algorithm.to_string()),
+            ),
+            (
+                "digits",
+                token(self, |t| t.digits.to_string()),
+                token(other, |t| t.digits.to_string()),
+            ),
+            (
+                "step",
+                token(self, |t| t.step.to_string()),
+                token(other, |t| t.step.to_string()),
+            ),
+            (
+                "skew",
+                token(self, |t| t.skew.to_string()),
+                token(other, |t| t.skew.to_string()),
+            ),
+        ];
+        let mut diff = fields
+            .into_iter()
+            .filter(|(_, old, new)| old != new)
+            .map(|(name, old, new)| format!("{}: {} -> {}", name, old, new))
+            .collect::<Vec<_>>();
+        let secret = |record: &Record| record.token.as_ref().map(|t| t.secret.clone());
+        if secret(self) != secret(other) {
+            diff.push("secret: changed".to_string());
+        }
+        if self.password != other.password {
+            diff.push("password: changed".to_string());
+        }
+        diff
+    }
+
     pub fn from_secure_record(
         secure_record: &SecureRecord,
         encryption: &Encryption,
-        password: &str,
+        key: &Key,
     ) -> Result<Record, TotpError> {
+        let id = secure_record.id;
+        let decrypt = |value: Option<&String>, field: &str| {
+            decrypt_record_field(value, key, encryption, &associated_data(id, field))
+        };
         Ok(Record {
-            id: secure_record.id,
-            account: decrypt_record_field(secure_record.account.as_ref(), password, encryption)?,
-            user: decrypt_record_field(secure_record.user.as_ref(), password, encryption)?,
-            token: decrypt_record_field(secure_record.token.as_ref(), password, encryption)?
+            id,
+            account: decrypt(secure_record.account.as_ref(), "account")?,
+            user: decrypt(secure_record.user.as_ref(), "user")?,
+            token: decrypt(secure_record.token.as_ref(), "token")?
                 .map(|t| serde_json::from_str(&t))
                 .and_then(|t| t.ok()),
-            password: decrypt_record_field(secure_record.password.as_ref(), password, encryption)?,
-            note: decrypt_record_field(secure_record.note.as_ref(), password, encryption)?,
+            password: decrypt(secure_record.password.as_ref(), "password")?,
+            note: decrypt(secure_record.note.as_ref(), "note")?,
             created_at: secure_record.created_at,
             updated_at: secure_record.updated_at,
+            counter: secure_record.counter,
         })
     }
 
+    /// Encrypt every field, bound to `self.id` so the id must be known before storing
     pub fn to_secure_record(
         &self,
         encryption: &Encryption,
-        password: &str,
+        key: &Key,
     ) -> Result<SecureRecord, TotpError> {
+        let encrypt = |value: Option<&String>, field: &str| {
+            encrypt_record_field(value, key, encryption, &associated_data(self.id, field))
+        };
         Ok(SecureRecord {
             id: self.id,
-            account: encrypt_record_field(self.account.as_ref(), password, encryption),
-            user: encrypt_record_field(self.user.as_ref(), password, encryption),
-            token: encrypt_record_field(
+            account: encrypt(self.account.as_ref(), "account")?,
+            user: encrypt(self.user.as_ref(), "user")?,
+            token: encrypt(
                 self.token
                     .as_ref()
                     .map(serde_json::to_string)
                     .and_then(|r| r.ok())
                     .as_ref(),
-                password,
-                encryption,
-            ),
-            password: encrypt_record_field(self.password.as_ref(), password, encryption),
-            note: encrypt_record_field(self.note.as_ref(), password, encryption),
+                "token",
+            )?,
+            password: encrypt(self.password.as_ref(), "password")?,
+            note: encrypt(self.note.as_ref(), "note")?,
             created_at: self.created_at,
             updated_at: self.updated_at,
+            counter: self.counter,
         })
     }
 }
+
+/// The associated data a record field is sealed with, so it cannot be moved to another row or column
+fn associated_data(id: u32, field: &str) -> String {
+    format!("secure_records:{}:{}", id, field)
+}
+
 pub fn decrypt_record_field<T: Display>(
     field_value: Option<&T>,
-    password: &str,
+    key: &Key,
     encryption: &Encryption,
+    associated_data: &str,
 ) -> Result<Option<String>, TotpError> {
     field_value
-        .map(|value| {
-            let mut parts = value
-                .to_string()
-                .splitn(2, ':')
-                .map(|s| s.to_string())
-                .collect::<Vec<String>>()
-                .into_iter();
-            let content = parts.next().unwrap_or_default();
-            let iv = parts.next().unwrap_or_default();
-            (content, iv)
-        })
-        .map(|(content, iv)| {
-            if !content.is_empty() && !iv.is_empty() {
-                encryption.decrypt(&content, password, &iv)
-            } else {
-                Ok("".to_string())
-            }
-        })
-        .map_or(Ok(None), |v| v.map(Some))
+        .map(|value| encryption.open(&value.to_string(), key, associated_data))
+        .transpose()
 }
 
 pub fn encrypt_record_field<T: Display>(
     field_value: Option<&T>,
-    password: &str,
+    key: &Key,
     encryption: &Encryption,
-) -> Option<String> {
+    associated_data: &str,
+) -> Result<Option<String>, TotpError> {
     field_value
-        .map(|value| encryption.encrypt(&value.to_string(), password))
-        .and_then(|res| res.ok())
-        .map(|(encrypted_content, iv)| format!("{}:{}", encrypted_content, iv))
+        .map(|value| encryption.seal(&value.to_string(), key, associated_data))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otp::token::Algorithm;
+    use std::str::FromStr;
+
+    #[test]
+    fn diff() {
+        let record = Record {
+            account: Some("GitHub".to_string()),
+            token: Some(Token::from_str("JBSWY3DPEHPK3PXP").unwrap()),
+            password: Some("hunter2".to_string()),
+            ..Record::default()
+        };
+        assert!(record.diff(&record).is_empty());
+        let edited = Record {
+            note: Some("work".to_string()),
+            token: Some(Token {
+                digits: 8,
+                algorithm: Algorithm::Sha256,
+                ..Token::from_str("KRSXG5A=").unwrap()
+            }),
+            password: Some("hunter3".to_string()),
+            ..record.clone()
+        };
+        assert_eq!(
+            record.diff(&edited),
+            vec![
+                "note: (none) -> work",
+                "algorithm: SHA1 -> SHA256",
+                "digits: 6 -> 8",
+                "secret: changed",
+                "password: changed",
+            ]
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use crate::db::encryption::Encryption;
 use crate::db::models::record::AccountName;
 use crate::db::Connection;
 use crate::TotpError;
@@ -16,6 +17,7 @@ pub struct SecureRecord {
     pub note: Option<EncryptedString>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub counter: u64,
 }
 
 impl SecureRecord {
@@ -29,6 +31,20 @@ impl SecureRecord {
         }
         Ok(result)
     }
+
+    /// Whether every field uses authenticated encryption
+    pub fn is_sealed(&self) -> bool {
+        [
+            &self.account,
+            &self.user,
+            &self.token,
+            &self.password,
+            &self.note,
+        ]
+        .into_iter()
+        .flatten()
+        .all(|value| Encryption::is_sealed(value))
+    }
 }
 
 impl From<&Row<'_>> for SecureRecord {
@@ -42,6 +58,7 @@ impl From<&Row<'_>> for SecureRecord {
             note: row.get(5).unwrap_or(None),
             created_at: NaiveDateTime::from_timestamp(row.get(6).unwrap(), 0),
             updated_at: NaiveDateTime::from_timestamp(row.get(7).unwrap(), 0),
+            counter: row.get::<_, i64>(8).unwrap_or_default() as u64,
         }
     }
 }
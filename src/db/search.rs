@@ -0,0 +1,235 @@
+use crate::Record;
+
+const SCORE_MATCH: i64 = 16;
+/// Matching the first character of a word, like the `h` in `GitHub` or `my-hub`
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_CONSECUTIVE: i64 = 8;
+/// The whole text matched, so exact names rank above longer ones
+const BONUS_EXACT: i64 = 16;
+const PENALTY_GAP: i64 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    Account,
+    User,
+    Note,
+}
+
+impl Field {
+    const ALL: [Field; 3] = [Field::Account, Field::User, Field::Note];
+
+    fn from_prefix(prefix: &str) -> Option<Self> {
+        match prefix.to_lowercase().as_str() {
+            "account" | "issuer" => Some(Field::Account),
+            "user" => Some(Field::User),
+            "note" => Some(Field::Note),
+            _ => None,
+        }
+    }
+
+    fn value<'a>(&self, record: &'a Record) -> Option<&'a str> {
+        match self {
+            Field::Account => record.account.as_deref(),
+            Field::User => record.user.as_deref(),
+            Field::Note => record.note.as_deref(),
+        }
+    }
+}
+
+/// How well a record matched
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Match {
+    pub score: i64,
+    /// Matched character positions in the account name
+    pub account: Vec<usize>,
+}
+
+/// Whitespace separated terms that must all match, `user:`, `note:` and `account:` or `issuer:`
+/// limit a term to one field
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    terms: Vec<(Option<Field>, Vec<char>)>,
+}
+
+impl Query {
+    pub fn new(query: &str) -> Self {
+        let terms = query
+            .split_whitespace()
+            .map(|term| match term.split_once(':') {
+                Some((prefix, rest)) => match Field::from_prefix(prefix) {
+                    Some(field) => (Some(field), rest),
+                    None => (None, term),
+                },
+                None => (None, term),
+            })
+            // A prefix on its own matches everything until something is typed after it
+            .filter(|(_, term)| !term.is_empty())
+            .map(|(field, term)| (field, term.chars().map(lowercase).collect()))
+            .collect();
+        Self { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn matches(&self, record: &Record) -> Option<Match> {
+        let mut result = Match::default();
+        for (field, pattern) in &self.terms {
+            let mut best = None;
+            for candidate in Field::ALL {
+                if field.is_some_and(|field| field != candidate) {
+                    continue;
+                }
+                let Some((score, positions)) = candidate
+                    .value(record)
+                    .and_then(|text| fuzzy(pattern, text))
+                else {
+                    continue;
+                };
+                if candidate == Field::Account {
+                    result.account.extend(positions);
+                }
+                best = best.max(Some(score));
+            }
+            result.score += best?;
+        }
+        result.account.sort_unstable();
+        result.account.dedup();
+        Some(result)
+    }
+}
+
+/// The records matching `query`, best first and then by account name
+pub fn search<'a>(
+    records: impl IntoIterator<Item = &'a Record>,
+    query: &str,
+) -> Vec<(&'a Record, Match)> {
+    let query = Query::new(query);
+    let mut matches = records
+        .into_iter()
+        .filter_map(|record| query.matches(record).map(|m| (record, m)))
+        .collect::<Vec<_>>();
+    matches.sort_by(|(a, a_match), (b, b_match)| {
+        b_match
+            .score
+            .cmp(&a_match.score)
+            .then_with(|| a.account.cmp(&b.account))
+    });
+    matches
+}
+
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Score `text` containing the lowercase `pattern` as a subsequence, with the matched positions
+///
+/// Like fzf's fast path, the first complete match is found and then shortened from its end,
+/// so the characters are matched as close together as possible.
+fn fuzzy(pattern: &[char], text: &str) -> Option<(i64, Vec<usize>)> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let lower = chars.iter().copied().map(lowercase).collect::<Vec<_>>();
+    if pattern.is_empty() {
+        return None;
+    }
+    let mut remaining = pattern.iter().peekable();
+    let end = lower.iter().position(|c| {
+        if remaining.peek() == Some(&c) {
+            remaining.next();
+        }
+        remaining.peek().is_none()
+    })?;
+    let mut remaining = pattern.iter().rev().peekable();
+    let start = (0..=end).rev().find(|i| {
+        if remaining.peek() == Some(&&lower[*i]) {
+            remaining.next();
+        }
+        remaining.peek().is_none()
+    })?;
+
+    let mut positions = Vec::with_capacity(pattern.len());
+    let mut score = 0;
+    let mut remaining = pattern.iter().peekable();
+    for i in start..=end {
+        if remaining.peek() != Some(&&lower[i]) {
+            continue;
+        }
+        remaining.next();
+        score += SCORE_MATCH;
+        let boundary = i == 0
+            || !chars[i - 1].is_alphanumeric()
+            || (chars[i - 1].is_lowercase() && chars[i].is_uppercase());
+        if boundary {
+            score += BONUS_BOUNDARY;
+        }
+        match positions.last() {
+            Some(previous) if previous + 1 == i => score += BONUS_CONSECUTIVE,
+            Some(previous) => score -= (i - previous - 1) as i64 * PENALTY_GAP,
+            None => {}
+        }
+        positions.push(i);
+    }
+    if positions.len() == lower.len() {
+        score += BONUS_EXACT;
+    }
+    Some((score, positions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(account: &str, user: Option<&str>, note: Option<&str>) -> Record {
+        Record {
+            account: Some(account.to_string()),
+            user: user.map(str::to_string),
+            note: note.map(str::to_string),
+            ..Record::default()
+        }
+    }
+
+    #[test]
+    fn fuzzy_scores() {
+        let pattern = |p: &str| p.chars().collect::<Vec<_>>();
+        assert_eq!(fuzzy(&pattern("gh"), "GitHub").unwrap().1, vec![0, 3]);
+        assert_eq!(fuzzy(&pattern("hub"), "GitHub").unwrap().1, vec![3, 4, 5]);
+        // The match is tightened to the last start before its end
+        assert_eq!(fuzzy(&pattern("ab"), "a-a-b").unwrap().1, vec![2, 4]);
+        assert!(fuzzy(&pattern("hg"), "GitHub").is_none());
+        let score = |p: &str, text: &str| fuzzy(&pattern(p), text).unwrap().0;
+        assert!(score("github", "GitHub") > score("github", "GitHub Enterprise"));
+        assert!(score("git", "GitLab") > score("git", "Digital Ocean"));
+        assert!(score("gl", "GitLab") > score("gl", "Google"));
+    }
+
+    #[test]
+    fn search_records() {
+        let records = vec![
+            record("GitHub", Some("alice"), None),
+            record("GitLab", Some("bob"), Some("prod deploys")),
+            record("Google", Some("alice@example.com"), Some("personal")),
+            record("AWS", Some("root"), Some("production")),
+        ];
+        let accounts = |query: &str| {
+            search(&records, query)
+                .into_iter()
+                .map(|(record, _)| record.account.clone().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(accounts("").len(), 4);
+        assert_eq!(accounts("gtlb"), vec!["GitLab"]);
+        assert_eq!(accounts("user:alice"), vec!["GitHub", "Google"]);
+        assert_eq!(accounts("prod"), vec!["AWS", "GitLab"]);
+        assert_eq!(accounts("note:prod git"), vec!["GitLab"]);
+        assert_eq!(accounts("issuer:aws"), vec!["AWS"]);
+        assert_eq!(accounts("note:"), accounts(""));
+        assert!(accounts("user:nobody").is_empty());
+
+        let (_, matched) = &search(&records, "ghb alice")[0];
+        assert_eq!(matched.account, vec![0, 3, 5]);
+        // Matching only the user highlights nothing in the account
+        let (_, matched) = &search(&records, "root")[0];
+        assert!(matched.account.is_empty());
+    }
+}
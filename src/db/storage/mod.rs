@@ -1,3 +1,4 @@
+use crate::db::kdf::Keyfile;
 use crate::db::models::record::AccountName;
 use crate::{Encryption, Record, TotpError};
 
@@ -11,15 +12,69 @@ pub trait StorageTrait {
             .cloned()
             .ok_or_else(|| TotpError::AccountNotFound(account_search.to_string()))
     }
+    /// The single record with this id or matching this name, preferring an exact name match
+    fn find_account(&self, account_or_id: &str) -> Result<Record, TotpError> {
+        if let Ok(id) = account_or_id.parse::<u32>() {
+            if let Ok(record) = self.get_account(id) {
+                return Ok(record);
+            }
+        }
+        let records = self.search_accounts(account_or_id)?;
+        if let [record] = records.as_slice() {
+            return Ok(record.clone());
+        }
+        let mut exact = records.iter().filter(|record| {
+            record
+                .account
+                .as_deref()
+                .is_some_and(|account| account.eq_ignore_ascii_case(account_or_id))
+        });
+        if let (Some(record), None) = (exact.next(), exact.next()) {
+            return Ok(record.clone());
+        }
+        if records.is_empty() {
+            return Err(TotpError::AccountNotFound(account_or_id.to_string()));
+        }
+        Err(TotpError::AmbiguousAccount(
+            records
+                .iter()
+                .map(|record| {
+                    format!(
+                        "{} {}",
+                        record.id,
+                        record.account.clone().unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+        ))
+    }
     fn search_accounts(&self, account_search: &str) -> Result<Vec<Record>, TotpError>;
     fn add_account(&mut self, record: Record) -> Result<(), TotpError>;
     fn edit_account(&mut self, record: Record) -> Result<(), TotpError>;
+    /// Write restored records in one transaction, records with an id of 0 are added as new accounts
+    ///
+    /// With `replace` every existing account is removed first and records keep their ids.
+    fn restore_accounts(&mut self, records: Vec<Record>, replace: bool) -> Result<(), TotpError>;
     fn remove_account(&mut self, account_or_id: String) -> Result<(), TotpError>;
     fn remove_account_by_name(&mut self, account: AccountName) -> Result<(), TotpError>;
     fn remove_account_by_id(&mut self, id: u32) -> Result<(), TotpError>;
+    /// Atomically advance the HOTP counter of a record, returning the value to generate with
+    fn increment_counter(&mut self, id: u32) -> Result<u64, TotpError>;
+    fn set_counter(&mut self, id: u32, counter: u64) -> Result<(), TotpError>;
     fn accounts(&self) -> Result<Vec<Record>, TotpError>;
     fn load(&mut self) -> Result<(), TotpError>;
     fn password(&self) -> &str;
+    /// Re-encrypt a vault still keyed by the padded password under a derived key
+    fn upgrade_encryption(&mut self) -> Result<bool, TotpError>;
+    /// Re-key the vault under a new password, after verifying the current one against the lock
+    fn change_password(&mut self, password: &str) -> Result<(), TotpError>;
+    /// Add, replace or with `None` remove the keyfile needed alongside the password
+    fn change_keyfile(&mut self, keyfile: Option<Keyfile>) -> Result<(), TotpError>;
+    /// Forget the password and key, so records cannot be decrypted until `unlock`
+    fn lock(&mut self);
+    /// Take the password again after `lock`, staying locked unless it opens the table lock
+    fn unlock(&mut self, password: &str) -> Result<(), TotpError>;
     fn get_encryption(&self) -> &Encryption;
     fn set_lock_encryption(&self) -> Result<(), TotpError>;
     fn verify_lock_encryption(&self) -> Result<(), TotpError>;
@@ -1,15 +1,32 @@
 use crate::db::encryption::Encryption;
+use crate::db::kdf::{KdfParams, Key, Keyfile};
 use crate::db::models::record::{decrypt_record_field, encrypt_record_field, AccountName};
 use crate::db::models::secure_record::SecureRecord;
+use crate::db::search::search;
 use crate::db::storage::StorageTrait;
 use crate::db::Connection;
 use crate::{Db, Record, TotpError};
-use r2d2_sqlite::rusqlite::params;
+use chrono::Utc;
+use r2d2_sqlite::rusqlite::{params, OptionalExtension, TransactionBehavior};
+
+/// The associated data the lock is sealed with, which also authenticates the vault's sealed flag
+///
+/// Clearing the flag in the `kdf` table to accept legacy fields again leaves a lock that no longer
+/// opens.
+fn lock_associated_data(sealed: bool) -> &'static str {
+    if sealed {
+        "table_lock:key:sealed"
+    } else {
+        "table_lock:key"
+    }
+}
 
 pub struct SqliteStorage {
     pub db: Db,
     secure_records: Vec<SecureRecord>,
     encryption: Encryption,
+    /// Read from the kdf row on `load`, legacy fields are only accepted until the vault is sealed
+    sealed: bool,
 }
 
 impl SqliteStorage {
@@ -18,7 +35,90 @@ impl SqliteStorage {
             db,
             secure_records: vec![],
             encryption,
+            sealed: false,
+        }
+    }
+
+    /// Re-encrypt every record and the lock under `key` in a single transaction
+    ///
+    /// Nothing is written unless every field could be re-encrypted, so the vault is never left
+    /// with a mix of old and new keys. The records are read again inside the transaction, which
+    /// holds the write lock, so accounts added by another process since `load` are included.
+    fn reencrypt(&mut self, key: Key, kdf_params: &KdfParams) -> Result<(), TotpError> {
+        let lock = encrypt_record_field(
+            Some(&self.encryption.key),
+            &key,
+            &self.encryption,
+            lock_associated_data(kdf_params.sealed),
+        )?;
+
+        const UPDATE_SQL: &str = r#"
+        UPDATE secure_records SET account = ?1, user = ?2, password = ?3, note = ?4, token = ?5
+            WHERE id = ?6;
+        "#;
+        let mut pooled = self.db.pool.get()?;
+        let conn =
+            Connection::from(pooled.transaction_with_behavior(TransactionBehavior::Immediate)?);
+        let records = self.decrypt_records(&SecureRecord::all(&conn)?)?;
+        for record in records {
+            let secure_record = record.to_secure_record(&Encryption::default(), &key)?;
+            conn.prepare(UPDATE_SQL)?.execute(params![
+                secure_record.account,
+                secure_record.user,
+                secure_record.password,
+                secure_record.note,
+                secure_record.token,
+                secure_record.id
+            ])?;
+        }
+        let has_lock = conn
+            .prepare("SELECT COUNT(*) FROM table_lock;")?
+            .query_row(params![], |row| row.get::<_, i64>(0))?
+            > 0;
+        if has_lock {
+            conn.prepare("DELETE FROM table_lock WHERE 1=1;")?
+                .execute(params![])?;
+            conn.prepare("INSERT INTO table_lock (key) VALUES (?1);")?
+                .execute(params![lock])?;
+        }
+        kdf_params.save(&conn)?;
+        conn.transaction()?.commit()?;
+
+        self.db.set_key(key);
+        self.load()
+    }
+
+    /// Re-encrypt the vault under a fresh salt derived from `password` and `keyfile`
+    fn rekey(&mut self, password: &str, keyfile: Option<Keyfile>) -> Result<(), TotpError> {
+        let kdf_params = KdfParams {
+            keyfile: keyfile.is_some(),
+            ..KdfParams::generate()?
+        };
+        let key = kdf_params.derive(password, keyfile.as_ref())?;
+        self.reencrypt(key, &kdf_params)?;
+        self.db.set_password(password.to_string());
+        self.db.set_keyfile(keyfile);
+        Ok(())
+    }
+
+    /// Decrypt records with the current key, refusing legacy fields once the vault is sealed
+    fn decrypt_records(&self, secure_records: &[SecureRecord]) -> Result<Vec<Record>, TotpError> {
+        let mut records = vec![];
+        let encryption = Encryption::default();
+        for rec in secure_records {
+            if self.sealed && !rec.is_sealed() {
+                return Err(TotpError::Decryption(format!(
+                    "Account {} is not sealed, it may have been tampered with",
+                    rec.id
+                )));
+            }
+            records.push(Record::from_secure_record(
+                rec,
+                &encryption,
+                self.db.key()?,
+            )?);
         }
+        Ok(records)
     }
 }
 
@@ -32,54 +132,53 @@ impl StorageTrait for SqliteStorage {
 
     fn search_accounts(&self, account_search: &str) -> Result<Vec<Record>, TotpError> {
         let accounts = self.accounts()?;
-        let mut accounts = accounts
-            .iter()
-            .filter(|record| {
-                record
-                    .account
-                    .clone()
-                    .unwrap_or_default()
-                    .to_lowercase()
-                    .contains(&account_search.to_lowercase())
-            })
-            .clone()
-            .collect::<Vec<&Record>>();
-        accounts.sort_by(|a, b| a.account.cmp(&b.account));
-
-        Ok(accounts.into_iter().cloned().collect::<Vec<_>>())
+        Ok(search(&accounts, account_search)
+            .into_iter()
+            .map(|(record, _)| record.clone())
+            .collect())
     }
 
     fn add_account(&mut self, record: Record) -> Result<(), TotpError> {
-        let secure_record = record.to_secure_record(&Encryption::default(), self.db.password())?;
-        const SQL: &str = r#"
+        const INSERT_SQL: &str = r#"
         INSERT INTO secure_records
-            (account, user, token, password, note, created_at, updated_at)
+            (counter, created_at, updated_at)
             VALUES
             (
              ?1,
-             ?2,
-             ?3,
-             ?4,
-             ?5,
              strftime('%s','now'),
              strftime('%s','now')
             );
         "#;
-        let conn = Connection::try_from(&self.db)?;
-        let mut stmt = conn.prepare(SQL)?;
-        stmt.execute(params![
+        const UPDATE_SQL: &str = r#"
+        UPDATE secure_records SET account = ?1, user = ?2, token = ?3, password = ?4, note = ?5
+            WHERE id = ?6;
+        "#;
+        let key = self.db.key()?.clone();
+        let mut pooled = self.db.pool.get()?;
+        // Fields are bound to the record id, which is only known once the row exists
+        let conn = Connection::from(pooled.transaction()?);
+        conn.prepare(INSERT_SQL)?
+            .execute(params![record.counter as i64])?;
+        let record = Record {
+            id: conn.last_insert_rowid() as u32,
+            ..record
+        };
+        let secure_record = record.to_secure_record(&Encryption::default(), &key)?;
+        conn.prepare(UPDATE_SQL)?.execute(params![
             secure_record.account,
             secure_record.user,
             secure_record.token,
             secure_record.password,
             secure_record.note,
+            secure_record.id,
         ])?;
+        conn.transaction()?.commit()?;
         self.load()?;
         Ok(())
     }
 
     fn edit_account(&mut self, record: Record) -> Result<(), TotpError> {
-        let secure_record = record.to_secure_record(&Encryption::default(), self.db.password())?;
+        let secure_record = record.to_secure_record(&Encryption::default(), self.db.key()?)?;
         const SQL: &str = r#"
         UPDATE secure_records SET account = ?1, user = ?2, password = ?3, note = ?4, token = ?5, updated_at = strftime('%s','now')
             WHERE id = ?6;
@@ -98,6 +197,61 @@ impl StorageTrait for SqliteStorage {
         Ok(())
     }
 
+    fn restore_accounts(&mut self, records: Vec<Record>, replace: bool) -> Result<(), TotpError> {
+        const DELETE_SQL: &str = "DELETE FROM secure_records;";
+        const INSERT_SQL: &str = r#"
+        INSERT INTO secure_records (id, counter, created_at, updated_at) VALUES (?1, ?2, ?3, ?4);
+        "#;
+        const UPDATE_SQL: &str = r#"
+        UPDATE secure_records SET account = ?1, user = ?2, token = ?3, password = ?4, note = ?5,
+            counter = ?6, updated_at = ?7
+            WHERE id = ?8;
+        "#;
+        // A backup is read from a file, so its secrets get the same checks as new accounts
+        for record in &records {
+            if let Some(token) = record.token.as_ref() {
+                token.validate()?;
+            }
+        }
+        let key = self.db.key()?.clone();
+        let mut pooled = self.db.pool.get()?;
+        let conn = Connection::from(pooled.transaction()?);
+        if replace {
+            conn.prepare(DELETE_SQL)?.execute([])?;
+        }
+        for record in records {
+            // New rows keep the backed up timestamps, merged ones are updated now
+            let (id, updated_at) = if replace || record.id == 0 {
+                conn.prepare(INSERT_SQL)?.execute(params![
+                    (record.id != 0 && replace).then_some(record.id),
+                    record.counter as i64,
+                    record.created_at.timestamp(),
+                    record.updated_at.timestamp(),
+                ])?;
+                (conn.last_insert_rowid() as u32, record.updated_at)
+            } else {
+                (record.id, Utc::now().naive_utc())
+            };
+            let secure_record =
+                Record { id, ..record }.to_secure_record(&Encryption::default(), &key)?;
+            let updated = conn.prepare(UPDATE_SQL)?.execute(params![
+                secure_record.account,
+                secure_record.user,
+                secure_record.token,
+                secure_record.password,
+                secure_record.note,
+                secure_record.counter as i64,
+                updated_at.timestamp(),
+                id,
+            ])?;
+            if updated == 0 {
+                return Err(TotpError::AccountNotFound(format!("id {} not found", id)));
+            }
+        }
+        conn.transaction()?.commit()?;
+        self.load()
+    }
+
     fn remove_account(&mut self, account_or_id: String) -> Result<(), TotpError> {
         if let Ok(id) = account_or_id.parse::<u32>() {
             return self.remove_account_by_id(id);
@@ -105,7 +259,7 @@ impl StorageTrait for SqliteStorage {
         self.remove_account_by_name(account_or_id)
     }
     fn remove_account_by_name(&mut self, account: AccountName) -> Result<(), TotpError> {
-        let account = self.search_account(&account)?;
+        let account = self.find_account(&account)?;
         self.remove_account_by_id(account.id)
     }
 
@@ -117,19 +271,41 @@ impl StorageTrait for SqliteStorage {
         self.load()
     }
 
+    fn increment_counter(&mut self, id: u32) -> Result<u64, TotpError> {
+        const SELECT_SQL: &str = "SELECT counter FROM secure_records WHERE id = ?1;";
+        const UPDATE_SQL: &str =
+            "UPDATE secure_records SET counter = counter + 1, updated_at = strftime('%s','now') WHERE id = ?1;";
+        let mut pooled = self.db.pool.get()?;
+        // Take the write lock up front so concurrent generators never share a counter
+        let conn =
+            Connection::from(pooled.transaction_with_behavior(TransactionBehavior::Immediate)?);
+        let counter = conn
+            .prepare(SELECT_SQL)?
+            .query_row(params![id], |row| row.get::<_, i64>(0))
+            .optional()?
+            .ok_or_else(|| TotpError::AccountNotFound(format!("id {} not found", id)))?;
+        conn.prepare(UPDATE_SQL)?.execute(params![id])?;
+        conn.transaction()?.commit()?;
+        self.load()?;
+        Ok(counter as u64)
+    }
+
+    fn set_counter(&mut self, id: u32, counter: u64) -> Result<(), TotpError> {
+        const SQL: &str = "UPDATE secure_records SET counter = ?1, updated_at = strftime('%s','now') WHERE id = ?2;";
+        let conn = Connection::try_from(&self.db)?;
+        let mut stmt = conn.prepare(SQL)?;
+        stmt.execute(params![counter as i64, id])?;
+        self.load()
+    }
+
     fn accounts(&self) -> Result<Vec<Record>, TotpError> {
-        let mut accounts = vec![];
-        let encryption = Encryption::default();
-        for rec in self.secure_records.iter() {
-            let record = Record::from_secure_record(rec, &encryption, self.db.password())?;
-            accounts.push(record);
-        }
-        Ok(accounts)
+        self.decrypt_records(&self.secure_records)
     }
 
     fn load(&mut self) -> Result<(), TotpError> {
         let conn = Connection::try_from(&self.db)?;
         self.secure_records = SecureRecord::all(&conn)?;
+        self.sealed = KdfParams::load(&conn)?.is_some_and(|params| params.sealed);
         Ok(())
     }
 
@@ -137,6 +313,50 @@ impl StorageTrait for SqliteStorage {
         self.db.password()
     }
 
+    fn upgrade_encryption(&mut self) -> Result<bool, TotpError> {
+        if self.db.is_legacy()? {
+            let password = self.db.password().to_string();
+            self.rekey(&password, None)?;
+            return Ok(true);
+        }
+        let kdf_params = KdfParams::load(&Connection::try_from(&self.db)?)?
+            .ok_or_else(|| TotpError::Decryption("Missing key derivation".to_string()))?;
+        if kdf_params.sealed {
+            return Ok(false);
+        }
+        // A fresh key, so legacy fields left over in old copies of the vault no longer decrypt
+        let password = self.db.password().to_string();
+        let keyfile = self.db.keyfile().cloned();
+        self.rekey(&password, keyfile)?;
+        Ok(true)
+    }
+
+    fn change_password(&mut self, password: &str) -> Result<(), TotpError> {
+        self.verify_lock_encryption()?;
+        let keyfile = self.db.keyfile().cloned();
+        self.rekey(password, keyfile)
+    }
+
+    fn change_keyfile(&mut self, keyfile: Option<Keyfile>) -> Result<(), TotpError> {
+        self.verify_lock_encryption()?;
+        let password = self.db.password().to_string();
+        self.rekey(&password, keyfile)
+    }
+
+    fn lock(&mut self) {
+        self.db.forget();
+    }
+
+    fn unlock(&mut self, password: &str) -> Result<(), TotpError> {
+        self.db.forget();
+        self.db.set_password(password.to_string());
+        if let Err(e) = self.verify_lock_encryption() {
+            self.db.forget();
+            return Err(e);
+        }
+        Ok(())
+    }
+
     fn get_encryption(&self) -> &Encryption {
         &self.encryption
     }
@@ -149,11 +369,13 @@ impl StorageTrait for SqliteStorage {
         const INSERT_SQL: &str = "INSERT INTO table_lock (key) VALUES (?1);";
         let mut stmt = conn.prepare(INSERT_SQL)?;
         let encryption = self.get_encryption();
+        let sealed = KdfParams::load(&conn)?.is_some_and(|params| params.sealed);
         stmt.execute(params![encrypt_record_field(
             Some(&encryption.key),
-            self.password(),
-            encryption
-        )])?;
+            self.db.key()?,
+            encryption,
+            lock_associated_data(sealed),
+        )?])?;
         Ok(())
     }
 
@@ -166,7 +388,18 @@ impl StorageTrait for SqliteStorage {
             let encryption = self.get_encryption();
             let value = result
                 .map_err(|_e| TotpError::Decryption("Missing lock key, aborting.".to_string()))?;
-            let key = decrypt_record_field(Some(&value), self.password(), encryption)?;
+            let sealed = KdfParams::load(&conn)?.is_some_and(|params| params.sealed);
+            if sealed && !Encryption::is_sealed(&value) {
+                return Err(TotpError::Decryption(
+                    "Lock key is not sealed, aborting.".to_string(),
+                ));
+            }
+            let key = decrypt_record_field(
+                Some(&value),
+                self.db.key()?,
+                encryption,
+                lock_associated_data(sealed),
+            )?;
             if key == Some(encryption.key.clone()) {
                 return Ok(());
             }
@@ -269,6 +502,453 @@ mod tests {
         assert!(token.is_err());
     }
 
+    #[test]
+    fn find_account() {
+        let mut storage = get_storage(None);
+        for account in ["GitHub", "GitHub Enterprise", "GitLab", "Google"] {
+            storage
+                .add_account(Record {
+                    account: Some(account.to_string()),
+                    ..Record::default()
+                })
+                .unwrap();
+        }
+        let account = |search: &str| storage.find_account(search).map(|r| r.account.unwrap());
+        assert_eq!(account("3").unwrap(), "GitLab");
+        assert_eq!(account("goo").unwrap(), "Google");
+        // An exact name wins over the other partial matches
+        assert_eq!(account("github").unwrap(), "GitHub");
+        assert!(matches!(
+            account("git"),
+            Err(TotpError::AmbiguousAccount(_))
+        ));
+        assert!(matches!(
+            account("bank"),
+            Err(TotpError::AccountNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn restore_accounts() {
+        let mut storage = get_storage(None);
+        for account in ["GitHub", "GitLab"] {
+            storage
+                .add_account(Record {
+                    account: Some(account.to_string()),
+                    ..Record::default()
+                })
+                .unwrap();
+        }
+        let backup = vec![
+            Record {
+                id: 2,
+                account: Some("GitLab".to_string()),
+                note: Some("restored".to_string()),
+                counter: 4,
+                ..Record::default()
+            },
+            Record {
+                id: 0,
+                account: Some("Google".to_string()),
+                ..Record::default()
+            },
+        ];
+        storage.restore_accounts(backup.clone(), false).unwrap();
+        let accounts = storage.accounts().unwrap();
+        assert_eq!(accounts.len(), 3);
+        let gitlab = storage.get_account(2).unwrap();
+        assert_eq!(gitlab.note, Some("restored".to_string()));
+        assert_eq!(gitlab.counter, 4);
+        assert_eq!(storage.find_account("Google").unwrap().id, 3);
+
+        // Updating an account that no longer exists rolls everything back
+        let missing = Record {
+            id: 9,
+            ..Record::default()
+        };
+        assert!(storage.restore_accounts(vec![missing], false).is_err());
+        assert_eq!(storage.accounts().unwrap().len(), 3);
+        let invalid = Record {
+            id: 0,
+            token: Some(Token {
+                step: 0,
+                ..Token::from_str("JBSWY3DPEHPK3PXP").unwrap()
+            }),
+            ..Record::default()
+        };
+        assert!(storage.restore_accounts(vec![invalid], true).is_err());
+        assert_eq!(storage.accounts().unwrap().len(), 3);
+
+        let backup = vec![Record {
+            id: 7,
+            ..backup[0].clone()
+        }];
+        storage.restore_accounts(backup, true).unwrap();
+        let accounts = storage.accounts().unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].id, 7);
+        assert_eq!(accounts[0].account, Some("GitLab".to_string()));
+    }
+
+    #[test]
+    fn increment_counter() {
+        let mut storage = get_storage(None);
+        storage
+            .add_account(Record {
+                account: Some("Account1".to_string()),
+                counter: 5,
+                ..Record::default()
+            })
+            .unwrap();
+        let id = storage.search_account("Account1").unwrap().id;
+        assert_eq!(storage.increment_counter(id).unwrap(), 5);
+        assert_eq!(storage.increment_counter(id).unwrap(), 6);
+        assert_eq!(storage.get_account(id).unwrap().counter, 7);
+        storage.set_counter(id, 2).unwrap();
+        assert_eq!(storage.get_account(id).unwrap().counter, 2);
+        assert!(storage.increment_counter(id + 1).is_err());
+    }
+
+    #[test]
+    fn upgrade_legacy_encryption() {
+        let db_path = format!("file:memdb{}?mode=memory&cache=shared", rand());
+        let db = Db::new("password".to_string(), Some(db_path.clone())).unwrap();
+        db.init().unwrap();
+        // Vaults created before key derivation have no kdf parameters
+        let conn = Connection::try_from(&db).unwrap();
+        conn.prepare("DELETE FROM kdf;")
+            .unwrap()
+            .execute([])
+            .unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        assert_eq!(storage.db.key().unwrap(), &Key::legacy("password"));
+        storage.set_lock_encryption().unwrap();
+        storage
+            .add_account(Record {
+                account: Some("Account1".to_string()),
+                token: Some(Token::from_str("JBSWY3DPEHPK3PXP").unwrap()),
+                ..Record::default()
+            })
+            .unwrap();
+        let legacy_secure_record = storage.secure_records[0].clone();
+
+        assert!(storage.upgrade_encryption().unwrap());
+        assert!(!storage.upgrade_encryption().unwrap());
+        assert_ne!(storage.db.key().unwrap(), &Key::legacy("password"));
+        assert_ne!(storage.secure_records[0].token, legacy_secure_record.token);
+
+        // A fresh connection derives the new key from the stored parameters
+        let db = Db::new("password".to_string(), Some(db_path.clone())).unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        storage.load().unwrap();
+        assert!(storage.verify_lock_encryption().is_ok());
+        let record = storage.search_account("Account1").unwrap();
+        assert_eq!(record.token.unwrap().to_string(), "JBSWY3DPEHPK3PXP");
+
+        let db = Db::new("wrong".to_string(), Some(db_path)).unwrap();
+        let storage = SqliteStorage::new(db, Encryption::default());
+        assert!(storage.verify_lock_encryption().is_err());
+    }
+
+    #[test]
+    fn seal_legacy_fields() {
+        let mut storage = get_storage(None);
+        let encryption = Encryption::default();
+        let legacy = |content: &str, key: &Key| {
+            let (content, iv) = encryption.encrypt(content, key).unwrap();
+            format!("{}:{}", content, iv)
+        };
+        // Fields written before authenticated encryption are plain `content:iv`
+        let key = storage.db.key().unwrap().clone();
+        let conn = Connection::try_from(&storage.db).unwrap();
+        conn.prepare("UPDATE kdf SET sealed = 0;")
+            .unwrap()
+            .execute([])
+            .unwrap();
+        conn.prepare(
+            "INSERT INTO secure_records (account, token, created_at, updated_at) VALUES (?1, ?2, 0, 0);",
+        )
+        .unwrap()
+        .execute(params![
+            legacy("Account1", &key),
+            legacy(
+                &serde_json::to_string(&Token::from_str("JBSWY3DPEHPK3PXP").unwrap()).unwrap(),
+                &key
+            )
+        ])
+        .unwrap();
+        let legacy_lock = legacy(&encryption.key, &key);
+        conn.prepare("INSERT INTO table_lock (key) VALUES (?1);")
+            .unwrap()
+            .execute(params![legacy_lock])
+            .unwrap();
+        storage.load().unwrap();
+        assert!(storage.verify_lock_encryption().is_ok());
+        assert!(!storage.secure_records[0].is_sealed());
+
+        assert!(storage.upgrade_encryption().unwrap());
+        assert!(!storage.upgrade_encryption().unwrap());
+        assert!(storage.secure_records[0].is_sealed());
+        assert!(storage.verify_lock_encryption().is_ok());
+        let record = storage.search_account("Account1").unwrap();
+        assert_eq!(record.token.unwrap().to_string(), "JBSWY3DPEHPK3PXP");
+        // Sealing moves to a fresh key, so fields copied from before no longer decrypt
+        let old_key = key;
+        let key = storage.db.key().unwrap().clone();
+        assert_ne!(key, old_key);
+
+        // Once sealed, a legacy or malformed field is tampering rather than an old vault
+        for value in [legacy("Account2", &key), "garbage".to_string()] {
+            conn.prepare("UPDATE secure_records SET note = ?1;")
+                .unwrap()
+                .execute(params![value])
+                .unwrap();
+            storage.load().unwrap();
+            assert!(matches!(storage.accounts(), Err(TotpError::Decryption(_))));
+        }
+        let sealed_lock = conn
+            .prepare("SELECT key FROM table_lock;")
+            .unwrap()
+            .query_row([], |row| row.get::<_, String>(0))
+            .unwrap();
+        conn.prepare("UPDATE table_lock SET key = ?1;")
+            .unwrap()
+            .execute(params![legacy(&encryption.key, &key)])
+            .unwrap();
+        assert!(matches!(
+            storage.verify_lock_encryption(),
+            Err(TotpError::Decryption(_))
+        ));
+
+        // Clearing the flag to accept legacy fields again is caught by the lock
+        conn.prepare("UPDATE kdf SET sealed = 0;")
+            .unwrap()
+            .execute([])
+            .unwrap();
+        for lock in [sealed_lock, legacy_lock] {
+            conn.prepare("UPDATE table_lock SET key = ?1;")
+                .unwrap()
+                .execute(params![lock])
+                .unwrap();
+            assert!(matches!(
+                storage.verify_lock_encryption(),
+                Err(TotpError::Decryption(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn swapped_fields() {
+        let mut storage = get_storage(None);
+        for (account, secret) in [("Account1", "JBSWY3DPEHPK3PXP"), ("Account2", "KRSXG5A=")] {
+            storage
+                .add_account(Record {
+                    account: Some(account.to_string()),
+                    token: Some(Token::from_str(secret).unwrap()),
+                    ..Record::default()
+                })
+                .unwrap();
+        }
+        assert!(storage.secure_records.iter().all(SecureRecord::is_sealed));
+        // Copying a sealed token onto another row is detected instead of silently accepted
+        let conn = Connection::try_from(&storage.db).unwrap();
+        conn.prepare("UPDATE secure_records SET token = (SELECT token FROM secure_records WHERE id = 1) WHERE id = 2;")
+            .unwrap()
+            .execute([])
+            .unwrap();
+        storage.load().unwrap();
+        assert!(matches!(storage.accounts(), Err(TotpError::Decryption(_))));
+    }
+
+    #[test]
+    fn change_password() {
+        let db_path = format!("file:memdb{}?mode=memory&cache=shared", rand());
+        let db = Db::new("password".to_string(), Some(db_path.clone())).unwrap();
+        db.init().unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        // The current password can only be verified against the lock
+        assert!(matches!(
+            storage.change_password("new password"),
+            Err(TotpError::MissingLockKey)
+        ));
+        storage.set_lock_encryption().unwrap();
+        storage
+            .add_account(Record {
+                account: Some("Account1".to_string()),
+                token: Some(Token::from_str("JBSWY3DPEHPK3PXP").unwrap()),
+                ..Record::default()
+            })
+            .unwrap();
+        storage.change_password("new password").unwrap();
+        assert_eq!(storage.password(), "new password");
+        assert!(storage.verify_lock_encryption().is_ok());
+
+        let db = Db::new("password".to_string(), Some(db_path.clone())).unwrap();
+        let storage = SqliteStorage::new(db, Encryption::default());
+        assert!(storage.verify_lock_encryption().is_err());
+
+        let db = Db::new("new password".to_string(), Some(db_path)).unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        storage.load().unwrap();
+        assert!(storage.verify_lock_encryption().is_ok());
+        let record = storage.search_account("Account1").unwrap();
+        assert_eq!(record.token.unwrap().to_string(), "JBSWY3DPEHPK3PXP");
+    }
+
+    #[test]
+    fn change_password_with_concurrent_add() {
+        let db_path = format!("file:memdb{}?mode=memory&cache=shared", rand());
+        let db = Db::new("password".to_string(), Some(db_path.clone())).unwrap();
+        db.init().unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        storage.set_lock_encryption().unwrap();
+        storage.load().unwrap();
+
+        // Another process adds an account after this one loaded the vault
+        let db = Db::new("password".to_string(), Some(db_path.clone())).unwrap();
+        let mut other = SqliteStorage::new(db, Encryption::default());
+        other
+            .add_account(Record {
+                account: Some("Account2".to_string()),
+                token: Some(Token::from_str("KRSXG5A=").unwrap()),
+                ..Record::default()
+            })
+            .unwrap();
+
+        storage.change_password("new password").unwrap();
+        let db = Db::new("new password".to_string(), Some(db_path)).unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        storage.load().unwrap();
+        let record = storage.search_account("Account2").unwrap();
+        assert_eq!(record.token.unwrap().to_string(), "KRSXG5A=");
+    }
+
+    #[test]
+    fn lock_and_unlock() {
+        let mut storage = get_storage(None);
+        storage.set_lock_encryption().unwrap();
+        storage
+            .add_account(Record {
+                account: Some("Account1".to_string()),
+                token: Some(Token::from_str("JBSWY3DPEHPK3PXP").unwrap()),
+                ..Record::default()
+            })
+            .unwrap();
+        storage.lock();
+        assert_eq!(storage.password(), "");
+        assert!(storage.accounts().is_err());
+        assert!(storage.unlock("wrong password").is_err());
+        assert!(storage.accounts().is_err());
+        storage.unlock("password").unwrap();
+        assert_eq!(storage.accounts().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn change_password_rollback() {
+        let db_path = format!("file:memdb{}?mode=memory&cache=shared", rand());
+        let db = Db::new("password".to_string(), Some(db_path.clone())).unwrap();
+        db.init().unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        storage.set_lock_encryption().unwrap();
+        for account in ["Account1", "Account2"] {
+            storage
+                .add_account(Record {
+                    account: Some(account.to_string()),
+                    ..Record::default()
+                })
+                .unwrap();
+        }
+        // A damaged record stops the re-key before anything is written
+        let conn = Connection::try_from(&storage.db).unwrap();
+        conn.prepare("UPDATE secure_records SET note = (SELECT account FROM secure_records WHERE id = 1) WHERE id = 2;")
+            .unwrap()
+            .execute([])
+            .unwrap();
+        storage.load().unwrap();
+        assert!(storage.change_password("new password").is_err());
+
+        let db = Db::new("password".to_string(), Some(db_path)).unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        storage.load().unwrap();
+        assert!(storage.verify_lock_encryption().is_ok());
+        storage.remove_account_by_id(2).unwrap();
+        assert_eq!(
+            storage.get_account(1).unwrap().account,
+            Some("Account1".to_string())
+        );
+    }
+
+    #[test]
+    fn keyfile() {
+        let db_path = format!("file:memdb{}?mode=memory&cache=shared", rand());
+        let db = Db::new("password".to_string(), Some(db_path.clone())).unwrap();
+        db.init().unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        storage.set_lock_encryption().unwrap();
+        storage
+            .add_account(Record {
+                account: Some("Account1".to_string()),
+                ..Record::default()
+            })
+            .unwrap();
+        let keyfile = Keyfile::generate().unwrap();
+        storage.change_keyfile(Some(keyfile.clone())).unwrap();
+        // Password changes keep the keyfile
+        storage.change_password("new password").unwrap();
+
+        let open = |keyfile: Option<Keyfile>| {
+            let mut db = Db::new("new password".to_string(), Some(db_path.clone())).unwrap();
+            db.set_keyfile(keyfile);
+            let mut storage = SqliteStorage::new(db, Encryption::default());
+            storage.load().unwrap();
+            storage
+        };
+        assert!(matches!(
+            open(None).verify_lock_encryption(),
+            Err(TotpError::Password(_))
+        ));
+        assert!(open(Some(Keyfile::generate().unwrap()))
+            .verify_lock_encryption()
+            .is_err());
+        let mut storage = open(Some(keyfile));
+        assert!(storage.verify_lock_encryption().is_ok());
+        assert_eq!(
+            storage.get_account(1).unwrap().account,
+            Some("Account1".to_string())
+        );
+
+        storage.change_keyfile(None).unwrap();
+        let storage = open(None);
+        assert!(storage.verify_lock_encryption().is_ok());
+        assert!(matches!(
+            open(Some(Keyfile::generate().unwrap())).verify_lock_encryption(),
+            Err(TotpError::Password(_))
+        ));
+    }
+
+    #[test]
+    fn long_password() {
+        let db = Db::new(
+            "a".repeat(64),
+            Some(format!("file:memdb{}?mode=memory&cache=shared", rand())),
+        )
+        .unwrap();
+        db.init().unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        storage.set_lock_encryption().unwrap();
+        storage
+            .add_account(Record {
+                account: Some("Account1".to_string()),
+                ..Record::default()
+            })
+            .unwrap();
+        assert!(storage.verify_lock_encryption().is_ok());
+        assert_eq!(
+            storage.accounts().unwrap()[0].account,
+            Some("Account1".to_string())
+        );
+    }
+
     #[test]
     fn table_lock_keys() {
         let db_path = format!("file:memdb{}?mode=memory&cache=shared", rand());
@@ -292,7 +972,7 @@ mod tests {
         assert!(storage.verify_lock_encryption().is_ok());
 
         let db = Db::new("password".to_string(), Some(db_path.clone())).unwrap();
-        let mut storage = SqliteStorage::new(
+        let storage = SqliteStorage::new(
             db,
             Encryption {
                 key: "SomeOtherKey".to_string(),
@@ -304,7 +984,7 @@ mod tests {
 
         // Now check the wrong password fails
         let db = Db::new("wrong_password".to_string(), Some(db_path.clone())).unwrap();
-        let mut storage = SqliteStorage::new(
+        let storage = SqliteStorage::new(
             db,
             Encryption {
                 key: "SomeOtherKey".to_string(),
@@ -317,7 +997,7 @@ mod tests {
 
         // Now check the wrong key fails
         let db = Db::new("password".to_string(), Some(db_path)).unwrap();
-        let mut storage = SqliteStorage::new(
+        let storage = SqliteStorage::new(
             db,
             Encryption {
                 key: "WrongKey".to_string(),
@@ -25,6 +25,9 @@ pub enum TotpError {
     #[allow(dead_code)]
     Utf8(String),
     InvalidOtpForRange,
+    InvalidOtpForWindow,
+    #[allow(dead_code)]
+    NotHotp(String),
     #[allow(dead_code)]
     Ui(String),
     #[allow(dead_code)]
@@ -43,6 +46,24 @@ pub enum TotpError {
     SecretParseError(String),
     #[allow(dead_code)]
     ClipboardError(String),
+    #[allow(dead_code)]
+    InvalidAlgorithm(String),
+    #[allow(dead_code)]
+    InvalidToken(String),
+    #[allow(dead_code)]
+    Qr(String),
+    #[allow(dead_code)]
+    Import(String),
+    #[allow(dead_code)]
+    Password(String),
+    #[allow(dead_code)]
+    Agent(String),
+    #[allow(dead_code)]
+    AmbiguousAccount(String),
+    #[allow(dead_code)]
+    Backup(String),
+    #[allow(dead_code)]
+    Config(String),
 }
 
 impl Error for TotpError {}
@@ -103,6 +124,12 @@ impl From<ErrorStack> for TotpError {
     }
 }
 
+impl From<toml::de::Error> for TotpError {
+    fn from(e: toml::de::Error) -> Self {
+        TotpError::Config(e.to_string())
+    }
+}
+
 impl From<RecvError> for TotpError {
     fn from(e: RecvError) -> Self {
         TotpError::UiEvent(e.to_string())
@@ -120,3 +147,9 @@ impl From<serde_json::Error> for TotpError {
         TotpError::Json(e.to_string())
     }
 }
+
+impl From<qrcode::types::QrError> for TotpError {
+    fn from(e: qrcode::types::QrError) -> Self {
+        TotpError::Qr(e.to_string())
+    }
+}
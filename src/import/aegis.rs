@@ -0,0 +1,246 @@
+use crate::db::kdf::scrypt_max_memory;
+use crate::import::{Entry, EntrySecret};
+use crate::TotpError;
+use data_encoding::{BASE64, HEXLOWER_PERMISSIVE};
+use openssl::pkcs5::scrypt;
+use openssl::symm::{decrypt_aead, Cipher};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Password slots are the only kind that can be unlocked without a device
+const PASSWORD_SLOT: u8 = 1;
+
+#[derive(Deserialize)]
+struct Vault {
+    header: Header,
+    db: Value,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    slots: Option<Vec<Slot>>,
+    params: Option<KeyParams>,
+}
+
+#[derive(Deserialize)]
+struct Slot {
+    #[serde(rename = "type")]
+    slot_type: u8,
+    key: String,
+    key_params: KeyParams,
+    n: Option<u64>,
+    r: Option<u64>,
+    p: Option<u64>,
+    salt: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct KeyParams {
+    nonce: String,
+    tag: String,
+}
+
+#[derive(Deserialize)]
+struct Database {
+    entries: Vec<AegisEntry>,
+}
+
+#[derive(Deserialize)]
+struct AegisEntry {
+    #[serde(rename = "type")]
+    entry_type: String,
+    name: Option<String>,
+    issuer: Option<String>,
+    note: Option<String>,
+    info: Info,
+}
+
+#[derive(Deserialize)]
+struct Info {
+    secret: String,
+    algo: Option<String>,
+    digits: Option<usize>,
+    period: Option<u64>,
+    counter: Option<u64>,
+}
+
+pub fn is_encrypted(contents: &str) -> bool {
+    serde_json::from_str::<Vault>(contents)
+        .map(|vault| vault.db.is_string())
+        .unwrap_or(false)
+}
+
+pub fn parse(contents: &str, password: Option<&str>) -> Result<Vec<Entry>, TotpError> {
+    let vault: Vault = serde_json::from_str(contents)?;
+    let database: Database = match vault.db {
+        Value::String(encrypted) => {
+            let password = password.ok_or_else(|| {
+                TotpError::Import(
+                    "The Aegis vault is encrypted, a password is required".to_string(),
+                )
+            })?;
+            serde_json::from_slice(&decrypt_database(&vault.header, &encrypted, password)?)?
+        }
+        db => serde_json::from_value(db)?,
+    };
+    Ok(database
+        .entries
+        .into_iter()
+        .map(|entry| Entry {
+            otp_type: entry.entry_type,
+            issuer: entry.issuer,
+            name: entry.name,
+            secret: EntrySecret::Base32(entry.info.secret),
+            algorithm: entry.info.algo,
+            digits: entry.info.digits,
+            period: entry.info.period,
+            counter: entry.info.counter,
+            note: entry.note,
+        })
+        .collect())
+}
+
+fn decrypt_database(
+    header: &Header,
+    encrypted: &str,
+    password: &str,
+) -> Result<Vec<u8>, TotpError> {
+    let params = header
+        .params
+        .as_ref()
+        .ok_or_else(|| TotpError::Import("Missing vault parameters".to_string()))?;
+    let master_key = header
+        .slots
+        .iter()
+        .flatten()
+        .filter(|slot| slot.slot_type == PASSWORD_SLOT)
+        .find_map(|slot| decrypt_slot(slot, password).ok())
+        .ok_or_else(|| TotpError::Decryption("Invalid Aegis vault password".to_string()))?;
+    aes_gcm_decrypt(&master_key, params, &BASE64.decode(encrypted.as_bytes())?)
+}
+
+fn decrypt_slot(slot: &Slot, password: &str) -> Result<Vec<u8>, TotpError> {
+    let (Some(n), Some(r), Some(p), Some(salt)) = (slot.n, slot.r, slot.p, slot.salt.as_ref())
+    else {
+        return Err(TotpError::Import("Missing scrypt parameters".to_string()));
+    };
+    let max_memory = scrypt_max_memory(n, r, p)
+        .ok_or_else(|| TotpError::Import("Unsupported scrypt parameters".to_string()))?;
+    let mut key = [0u8; 32];
+    scrypt(
+        password.as_bytes(),
+        &hex(salt)?,
+        n,
+        r,
+        p,
+        max_memory,
+        &mut key,
+    )?;
+    aes_gcm_decrypt(&key, &slot.key_params, &hex(&slot.key)?)
+}
+
+fn aes_gcm_decrypt(key: &[u8], params: &KeyParams, data: &[u8]) -> Result<Vec<u8>, TotpError> {
+    decrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&hex(&params.nonce)?),
+        &[],
+        data,
+        &hex(&params.tag)?,
+    )
+    .map_err(|_| TotpError::Decryption("Invalid Aegis vault password".to_string()))
+}
+
+fn hex(value: &str) -> Result<Vec<u8>, TotpError> {
+    Ok(HEXLOWER_PERMISSIVE.decode(value.as_bytes())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{parse as parse_import, ImportFormat};
+    use openssl::symm::encrypt_aead;
+
+    const DB: &str = r#"{"version":2,"entries":[
+        {"type":"totp","uuid":"1","name":"alice@example.com","issuer":"Google","note":"Work","icon":null,
+         "info":{"secret":"JBSWY3DPEHPK3PXP","algo":"SHA256","digits":8,"period":60}},
+        {"type":"hotp","uuid":"2","name":"vpn","issuer":"","note":"",
+         "info":{"secret":"GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ","algo":"SHA1","digits":6,"counter":12}},
+        {"type":"steam","uuid":"3","name":"gaben","issuer":"Steam","note":"",
+         "info":{"secret":"JBSWY3DPEHPK3PXP","algo":"SHA1","digits":5,"period":30}}
+    ]}"#;
+
+    fn encrypt_hex(key: &[u8], data: &[u8]) -> (String, String, String) {
+        let nonce = [7u8; 12];
+        let mut tag = [0u8; 16];
+        let encrypted = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            key,
+            Some(&nonce),
+            &[],
+            data,
+            &mut tag,
+        )
+        .unwrap();
+        (
+            HEXLOWER_PERMISSIVE.encode(&encrypted),
+            HEXLOWER_PERMISSIVE.encode(&nonce),
+            HEXLOWER_PERMISSIVE.encode(&tag),
+        )
+    }
+
+    fn encrypted_vault(password: &str) -> String {
+        let master_key = [3u8; 32];
+        let salt = [9u8; 32];
+        let mut slot_key = [0u8; 32];
+        scrypt(password.as_bytes(), &salt, 1024, 8, 1, 0, &mut slot_key).unwrap();
+        let (key, key_nonce, key_tag) = encrypt_hex(&slot_key, &master_key);
+        let (db, nonce, tag) = encrypt_hex(&master_key, DB.as_bytes());
+        let db = BASE64.encode(&HEXLOWER_PERMISSIVE.decode(db.as_bytes()).unwrap());
+        serde_json::json!({
+            "version": 1,
+            "header": {
+                "slots": [{
+                    "type": 1, "uuid": "slot", "key": key,
+                    "key_params": {"nonce": key_nonce, "tag": key_tag},
+                    "n": 1024, "r": 8, "p": 1, "salt": HEXLOWER_PERMISSIVE.encode(&salt)
+                }],
+                "params": {"nonce": nonce, "tag": tag}
+            },
+            "db": db
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn parse_plaintext() {
+        let contents = format!(
+            r#"{{"version":1,"header":{{"slots":null,"params":null}},"db":{}}}"#,
+            DB
+        );
+        assert!(!is_encrypted(&contents));
+        let records = parse_import(ImportFormat::Aegis, &contents, None).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].account, Some("Google".to_string()));
+        assert_eq!(records[0].user, Some("alice@example.com".to_string()));
+        assert_eq!(records[0].note, Some("Work".to_string()));
+        assert_eq!(records[0].token.as_ref().unwrap().step, 60);
+        assert_eq!(records[1].account, Some("vpn".to_string()));
+        assert_eq!(records[1].counter, 12);
+    }
+
+    #[test]
+    fn parse_encrypted() {
+        let contents = encrypted_vault("vault password");
+        assert!(is_encrypted(&contents));
+        assert!(parse(&contents, None).is_err());
+        assert!(parse(&contents, Some("wrong")).is_err());
+        let entries = parse(&contents, Some("vault password")).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name, Some("alice@example.com".to_string()));
+
+        // Hostile slot parameters fail instead of overflowing
+        let hostile = contents.replace(r#""r":8"#, r#""r":18446744073709551615"#);
+        assert!(parse(&hostile, Some("vault password")).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use crate::import::{Entry, EntrySecret};
+use crate::TotpError;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct AndOtpEntry {
+    secret: String,
+    #[serde(default)]
+    issuer: String,
+    #[serde(default)]
+    label: String,
+    digits: Option<usize>,
+    #[serde(rename = "type")]
+    entry_type: String,
+    algorithm: Option<String>,
+    period: Option<u64>,
+    counter: Option<u64>,
+}
+
+pub fn parse(contents: &str) -> Result<Vec<Entry>, TotpError> {
+    let entries: Vec<AndOtpEntry> = serde_json::from_str(contents)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            // Older backups have no issuer field and keep it as a label prefix instead
+            let (issuer, name) = match entry.label.split_once(':') {
+                Some((issuer, name)) if entry.issuer.is_empty() || entry.issuer == issuer => {
+                    (issuer.to_string(), name.trim_start().to_string())
+                }
+                _ => (entry.issuer, entry.label),
+            };
+            Entry {
+                otp_type: entry.entry_type,
+                issuer: Some(issuer),
+                name: Some(name),
+                secret: EntrySecret::Base32(entry.secret),
+                algorithm: entry.algorithm,
+                digits: entry.digits,
+                period: entry.period,
+                counter: entry.counter,
+                note: None,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::import::{parse, ImportFormat};
+
+    #[test]
+    fn parse_backup() {
+        let contents = r#"[
+            {"secret":"JBSWY3DPEHPK3PXP","issuer":"Google","label":"alice@example.com","digits":6,
+             "type":"TOTP","algorithm":"SHA512","thumbnail":"Default","last_used":0,"period":30,"tags":[]},
+            {"secret":"GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ","label":"VPN:bob","digits":8,
+             "type":"HOTP","algorithm":"SHA1","counter":4,"tags":["work"]}
+        ]"#;
+        let records = parse(ImportFormat::Andotp, contents, None).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].account, Some("Google".to_string()));
+        assert_eq!(records[0].user, Some("alice@example.com".to_string()));
+        assert_eq!(records[1].account, Some("VPN".to_string()));
+        assert_eq!(records[1].user, Some("bob".to_string()));
+        assert_eq!(records[1].counter, 4);
+        assert_eq!(records[1].token.as_ref().unwrap().digits, 8);
+    }
+}
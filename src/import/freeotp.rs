@@ -0,0 +1,66 @@
+use crate::import::{Entry, EntrySecret};
+use crate::TotpError;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Backup {
+    tokens: Vec<FreeOtpToken>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FreeOtpToken {
+    algo: Option<String>,
+    counter: Option<u64>,
+    digits: Option<usize>,
+    issuer_ext: Option<String>,
+    issuer_int: Option<String>,
+    label: Option<String>,
+    period: Option<u64>,
+    // Java bytes, so stored signed
+    secret: Vec<i8>,
+    #[serde(rename = "type")]
+    token_type: String,
+}
+
+pub fn parse(contents: &str) -> Result<Vec<Entry>, TotpError> {
+    let backup: Backup = serde_json::from_str(contents)?;
+    Ok(backup
+        .tokens
+        .into_iter()
+        .map(|token| Entry {
+            otp_type: token.token_type,
+            issuer: token
+                .issuer_ext
+                .filter(|i| !i.is_empty())
+                .or(token.issuer_int),
+            name: token.label,
+            secret: EntrySecret::Bytes(token.secret.into_iter().map(|b| b as u8).collect()),
+            algorithm: token.algo,
+            digits: token.digits,
+            period: token.period,
+            counter: token.counter,
+            note: None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::import::{parse, ImportFormat};
+
+    #[test]
+    fn parse_backup() {
+        let contents = r#"{"tokenOrder":["Google:alice"],"tokens":[
+            {"algo":"SHA256","counter":0,"digits":6,"issuerExt":"Google","issuerInt":"","label":"alice",
+             "period":30,"secret":[72,101,108,108,111,33,-34,-83,-66,-17],"type":"TOTP"}
+        ]}"#;
+        let records = parse(ImportFormat::Freeotp, contents, None).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].account, Some("Google".to_string()));
+        assert_eq!(records[0].user, Some("alice".to_string()));
+        let token = records[0].token.as_ref().unwrap();
+        assert_eq!(token.secret, b"Hello!\xde\xad\xbe\xef".to_vec());
+        assert_eq!(token.to_string(), "JBSWY3DPEHPK3PXP");
+    }
+}
@@ -0,0 +1,187 @@
+use crate::db::models::record::Record;
+use crate::otp::otpauth::OtpAuthUri;
+use crate::otp::token::{OtpType, Token};
+use crate::TotpError;
+use clap::ValueEnum;
+use std::str::FromStr;
+
+pub mod aegis;
+pub mod andotp;
+pub mod freeotp;
+pub mod twofas;
+
+/// Backup formats of other authenticator apps that can be imported
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ImportFormat {
+    /// Aegis JSON export, plaintext or encrypted
+    Aegis,
+    /// andOTP plaintext JSON backup
+    Andotp,
+    /// 2FAS plaintext JSON backup
+    #[value(name = "2fas")]
+    TwoFas,
+    /// FreeOTP+ JSON backup
+    Freeotp,
+}
+
+/// Parse an exported backup into records, `password` is only used for encrypted Aegis vaults
+pub fn parse(
+    format: ImportFormat,
+    contents: &str,
+    password: Option<&str>,
+) -> Result<Vec<Record>, TotpError> {
+    let entries = match format {
+        ImportFormat::Aegis => aegis::parse(contents, password)?,
+        ImportFormat::Andotp => andotp::parse(contents)?,
+        ImportFormat::TwoFas => twofas::parse(contents)?,
+        ImportFormat::Freeotp => freeotp::parse(contents)?,
+    };
+    let mut records = vec![];
+    for entry in entries {
+        let name = entry.display_name();
+        match entry.into_record() {
+            Ok(Some(record)) => records.push(record),
+            Ok(None) => log::warn!("Skipping {}, only TOTP and HOTP are supported", name),
+            Err(e) => return Err(TotpError::Import(format!("{}: {}", name, e))),
+        }
+    }
+    Ok(records)
+}
+
+/// An account as the other apps describe it, before it is mapped onto a `Record`
+#[derive(Default)]
+pub struct Entry {
+    pub otp_type: String,
+    pub issuer: Option<String>,
+    pub name: Option<String>,
+    pub secret: EntrySecret,
+    pub algorithm: Option<String>,
+    pub digits: Option<usize>,
+    pub period: Option<u64>,
+    pub counter: Option<u64>,
+    pub note: Option<String>,
+}
+
+#[derive(Default)]
+pub enum EntrySecret {
+    #[default]
+    Missing,
+    Base32(String),
+    Bytes(Vec<u8>),
+}
+
+impl Entry {
+    fn display_name(&self) -> String {
+        [self.issuer.as_deref(), self.name.as_deref()]
+            .into_iter()
+            .flatten()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    /// Returns `None` for OTP types trotp cannot generate, such as Steam
+    fn into_record(self) -> Result<Option<Record>, TotpError> {
+        let otp_type = match self.otp_type.to_lowercase().as_str() {
+            "totp" => OtpType::Totp,
+            "hotp" => OtpType::Hotp,
+            _ => return Ok(None),
+        };
+        let secret = match self.secret {
+            EntrySecret::Base32(secret) => Token::from_str(&secret.replace(' ', ""))?.secret,
+            EntrySecret::Bytes(secret) => secret,
+            EntrySecret::Missing => {
+                return Err(TotpError::Import("Missing secret".to_string()));
+            }
+        };
+        let default = Token::default();
+        let token = Token {
+            secret,
+            digits: self.digits.unwrap_or(default.digits),
+            step: self.period.unwrap_or(default.step),
+            algorithm: match self.algorithm {
+                Some(algorithm) => algorithm.parse()?,
+                None => default.algorithm,
+            },
+            otp_type,
+            ..default
+        };
+        token.validate()?;
+        let issuer = self.issuer.filter(|i| !i.is_empty());
+        let name = self.name.unwrap_or_default();
+        // Fall back to the issuer so every record has an account name
+        let (issuer, label) = match (issuer, name.is_empty()) {
+            (Some(issuer), true) => (None, issuer),
+            (issuer, _) => (issuer, name),
+        };
+        let record = Record::from(OtpAuthUri {
+            issuer,
+            label,
+            token,
+            counter: self.counter.unwrap_or_default(),
+        });
+        Ok(Some(Record {
+            note: self.note.filter(|n| !n.is_empty()),
+            ..record
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_into_record() {
+        let record = Entry {
+            otp_type: "TOTP".to_string(),
+            issuer: Some("Google".to_string()),
+            name: Some("alice@example.com".to_string()),
+            secret: EntrySecret::Base32("jbsw y3dp ehpk 3pxp".to_string()),
+            algorithm: Some("SHA256".to_string()),
+            digits: Some(8),
+            period: Some(60),
+            note: Some("Work".to_string()),
+            ..Entry::default()
+        }
+        .into_record()
+        .unwrap()
+        .unwrap();
+        assert_eq!(record.account, Some("Google".to_string()));
+        assert_eq!(record.user, Some("alice@example.com".to_string()));
+        assert_eq!(record.note, Some("Work".to_string()));
+        let token = record.token.unwrap();
+        assert_eq!(token.to_string(), "JBSWY3DPEHPK3PXP");
+        assert_eq!(token.digits, 8);
+        assert_eq!(token.step, 60);
+
+        let record = Entry {
+            otp_type: "totp".to_string(),
+            issuer: Some("GitHub".to_string()),
+            secret: EntrySecret::Bytes(vec![1; 10]),
+            ..Entry::default()
+        }
+        .into_record()
+        .unwrap()
+        .unwrap();
+        assert_eq!(record.account, Some("GitHub".to_string()));
+        assert_eq!(record.user, None);
+
+        let steam = Entry {
+            otp_type: "steam".to_string(),
+            ..Entry::default()
+        };
+        assert!(steam.into_record().unwrap().is_none());
+
+        for (digits, period) in [(Some(12), None), (None, Some(0))] {
+            let entry = Entry {
+                otp_type: "totp".to_string(),
+                secret: EntrySecret::Bytes(vec![1; 10]),
+                digits,
+                period,
+                ..Entry::default()
+            };
+            assert!(entry.into_record().is_err());
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::import::{Entry, EntrySecret};
+use crate::TotpError;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Backup {
+    #[serde(default)]
+    services: Vec<Service>,
+    services_encrypted: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Service {
+    name: String,
+    secret: String,
+    otp: Otp,
+    notes: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Otp {
+    account: Option<String>,
+    label: Option<String>,
+    issuer: Option<String>,
+    digits: Option<usize>,
+    period: Option<u64>,
+    algorithm: Option<String>,
+    counter: Option<u64>,
+    token_type: Option<String>,
+}
+
+pub fn parse(contents: &str) -> Result<Vec<Entry>, TotpError> {
+    let backup: Backup = serde_json::from_str(contents)?;
+    if backup.services.is_empty() && backup.services_encrypted.is_some() {
+        return Err(TotpError::Import(
+            "Encrypted 2FAS backups are not supported, export without a password".to_string(),
+        ));
+    }
+    Ok(backup
+        .services
+        .into_iter()
+        .map(|service| Entry {
+            otp_type: service.otp.token_type.unwrap_or_else(|| "TOTP".to_string()),
+            issuer: service.otp.issuer.or(Some(service.name)),
+            name: service.otp.account.or(service.otp.label),
+            secret: EntrySecret::Base32(service.secret),
+            algorithm: service.otp.algorithm,
+            digits: service.otp.digits,
+            period: service.otp.period,
+            counter: service.otp.counter,
+            note: service.notes,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::import::{parse, ImportFormat};
+
+    #[test]
+    fn parse_backup() {
+        let contents = r#"{"schemaVersion":4,"appVersionCode":5000000,"services":[
+            {"name":"GitHub","secret":"JBSWY3DPEHPK3PXP","updatedAt":0,"notes":"Recovery codes in the safe",
+             "otp":{"label":"octocat","account":"octocat","issuer":"GitHub","digits":6,"period":30,
+                    "algorithm":"SHA1","tokenType":"TOTP","source":"Link"},
+             "order":{"position":0},"icon":{"selected":"Label"}},
+            {"name":"Bank","secret":"GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ",
+             "otp":{"digits":6,"algorithm":"SHA1","counter":3,"tokenType":"HOTP"}}
+        ],"groups":[]}"#;
+        let records = parse(ImportFormat::TwoFas, contents, None).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].account, Some("GitHub".to_string()));
+        assert_eq!(records[0].user, Some("octocat".to_string()));
+        assert_eq!(
+            records[0].note,
+            Some("Recovery codes in the safe".to_string())
+        );
+        assert_eq!(records[1].account, Some("Bank".to_string()));
+        assert_eq!(records[1].counter, 3);
+    }
+
+    #[test]
+    fn encrypted_backup() {
+        let contents = r#"{"schemaVersion":4,"services":[],"servicesEncrypted":"abc:def:ghi"}"#;
+        assert!(parse(ImportFormat::TwoFas, contents, None).is_err());
+    }
+}
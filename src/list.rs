@@ -0,0 +1,344 @@
+use crate::db::models::record::Record;
+use crate::otp::generator::Generator;
+use crate::TotpError;
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use std::cmp::Ordering;
+
+/// Columns `list` can show, `secret` and `password` are only shown with `--reveal`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Field {
+    Id,
+    Account,
+    User,
+    Note,
+    Code,
+    /// Seconds until the code changes
+    Expiry,
+    Type,
+    Algorithm,
+    Digits,
+    Step,
+    Counter,
+    Created,
+    Updated,
+    Secret,
+    Password,
+}
+
+pub const DEFAULT_FIELDS: [Field; 5] = [
+    Field::Id,
+    Field::Account,
+    Field::User,
+    Field::Code,
+    Field::Expiry,
+];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    #[default]
+    Table,
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    Csv,
+    Tsv,
+}
+
+impl Field {
+    pub fn name(&self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn is_secret(&self) -> bool {
+        matches!(self, Field::Secret | Field::Password)
+    }
+
+    /// The value of this column for a record, HOTP accounts have no code without advancing the counter
+    pub fn value(&self, record: &Record, now: u64) -> Result<Value, TotpError> {
+        let token = record.token.as_ref();
+        let totp = || -> Result<Option<(String, u64)>, TotpError> {
+            match token.cloned().map(Generator::new).transpose()? {
+                Some(generator) if !generator.is_hotp() => generator.generate(Some(now)).map(Some),
+                _ => Ok(None),
+            }
+        };
+        Ok(match self {
+            Field::Id => json!(record.id),
+            Field::Account => json!(record.account),
+            Field::User => json!(record.user),
+            Field::Note => json!(record.note),
+            Field::Code => json!(totp()?.map(|(code, _)| code)),
+            Field::Expiry => json!(totp()?.map(|(_, expiry)| expiry)),
+            Field::Type => json!(token.map(|t| t.otp_type.to_string())),
+            Field::Algorithm => json!(token.map(|t| t.algorithm.to_string())),
+            Field::Digits => json!(token.map(|t| t.digits)),
+            Field::Step => json!(token.map(|t| t.step)),
+            Field::Counter => json!(record.counter),
+            Field::Created => json!(record.created_at.format("%Y-%m-%d %H:%M:%S").to_string()),
+            Field::Updated => json!(record.updated_at.format("%Y-%m-%d %H:%M:%S").to_string()),
+            Field::Secret => json!(token.map(|t| t.to_string())),
+            Field::Password => json!(record.password),
+        })
+    }
+}
+
+/// The fields to show, refusing secret fields unless they were revealed
+pub fn fields(requested: &[Field], reveal: bool) -> Result<Vec<Field>, TotpError> {
+    if requested.is_empty() {
+        let mut fields = DEFAULT_FIELDS.to_vec();
+        if reveal {
+            fields.extend([Field::Secret, Field::Password]);
+        }
+        return Ok(fields);
+    }
+    if let Some(field) = requested.iter().find(|f| f.is_secret() && !reveal) {
+        return Err(TotpError::Format(format!(
+            "{} is only shown with --reveal",
+            field.name()
+        )));
+    }
+    Ok(requested.to_vec())
+}
+
+/// Sort records by a field, missing values last
+pub fn sort(records: &mut [Record], field: Field, now: u64) -> Result<(), TotpError> {
+    let mut keyed = records
+        .iter()
+        .map(|record| Ok((field.value(record, now)?, record.clone())))
+        .collect::<Result<Vec<_>, TotpError>>()?;
+    keyed.sort_by(|(a, _), (b, _)| compare(a, b));
+    for (record, (_, sorted)) in records.iter_mut().zip(keyed) {
+        *record = sorted;
+    }
+    Ok(())
+}
+
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (a, b) => text(a).to_lowercase().cmp(&text(b).to_lowercase()),
+    }
+}
+
+pub fn render(
+    records: &[Record],
+    fields: &[Field],
+    format: ListFormat,
+    now: u64,
+) -> Result<String, TotpError> {
+    let rows = records
+        .iter()
+        .map(|record| {
+            fields
+                .iter()
+                .map(|field| field.value(record, now))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let object = |row: &Vec<Value>| {
+        fields
+            .iter()
+            .map(Field::name)
+            .zip(row.iter().cloned())
+            .collect::<Map<_, _>>()
+    };
+    Ok(match format {
+        ListFormat::Table => table(fields, &rows),
+        ListFormat::Json => {
+            serde_json::to_string_pretty(&rows.iter().map(object).collect::<Vec<_>>())?
+        }
+        ListFormat::Jsonl => rows
+            .iter()
+            .map(|row| Value::Object(object(row)).to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        ListFormat::Csv => delimited(fields, &rows, ',', csv_escape),
+        ListFormat::Tsv => delimited(fields, &rows, '\t', tsv_escape),
+    })
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => "".to_string(),
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn table(fields: &[Field], rows: &[Vec<Value>]) -> String {
+    let header = fields
+        .iter()
+        .map(|field| field.name().to_uppercase())
+        .collect::<Vec<_>>();
+    // Keep every record on one line
+    let rows = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|value| text(value).replace(['\t', '\n', '\r'], " "))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let widths = header
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain([name.chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    [header]
+        .iter()
+        .chain(rows.iter())
+        .map(|row| {
+            row.iter()
+                .zip(widths.iter())
+                .map(|(cell, width)| format!("{: <width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join(" | ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn delimited(
+    fields: &[Field],
+    rows: &[Vec<Value>],
+    delimiter: char,
+    escape: fn(&str) -> String,
+) -> String {
+    let header = fields
+        .iter()
+        .map(|field| escape(&field.name()))
+        .collect::<Vec<_>>()
+        .join(&delimiter.to_string());
+    [header]
+        .into_iter()
+        .chain(rows.iter().map(|row| {
+            row.iter()
+                .map(|value| escape(&text(value)))
+                .collect::<Vec<_>>()
+                .join(&delimiter.to_string())
+        }))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// RFC 4180 quoting
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// TSV cannot quote, so tabs and line breaks are escaped instead
+fn tsv_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otp::token::{OtpType, Token};
+    use std::str::FromStr;
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                id: 1,
+                account: Some("GitHub".to_string()),
+                user: Some("alice, \"work\"".to_string()),
+                token: Some(Token::from_str("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap()),
+                password: Some("hunter2".to_string()),
+                ..Record::default()
+            },
+            Record {
+                id: 2,
+                account: Some("bank".to_string()),
+                note: Some("line\tone\nline two".to_string()),
+                token: Some(Token {
+                    otp_type: OtpType::Hotp,
+                    ..Token::from_str("JBSWY3DPEHPK3PXP").unwrap()
+                }),
+                ..Record::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn reveal_fields() {
+        assert_eq!(fields(&[], false).unwrap(), DEFAULT_FIELDS.to_vec());
+        assert!(fields(&[], true).unwrap().contains(&Field::Secret));
+        assert!(fields(&[Field::Account, Field::Secret], false).is_err());
+        assert_eq!(
+            fields(&[Field::Password], true).unwrap(),
+            vec![Field::Password]
+        );
+    }
+
+    #[test]
+    fn render_formats() {
+        let records = records();
+        let fields = [Field::Id, Field::User, Field::Code, Field::Note];
+        // RFC 6238 test vector at 59 seconds
+        let table = render(&records, &fields, ListFormat::Table, 59).unwrap();
+        assert_eq!(
+            table,
+            "ID | USER          | CODE   | NOTE\n\
+             1  | alice, \"work\" | 287082 |\n\
+             2  |               |        | line one line two"
+        );
+        let csv = render(&records, &fields, ListFormat::Csv, 59).unwrap();
+        assert_eq!(
+            csv,
+            "id,user,code,note\n1,\"alice, \"\"work\"\"\",287082,\n2,,,\"line\tone\nline two\""
+        );
+        let tsv = render(&records, &fields, ListFormat::Tsv, 59).unwrap();
+        assert_eq!(
+            tsv,
+            "id\tuser\tcode\tnote\n1\talice, \"work\"\t287082\t\n2\t\t\tline\\tone\\nline two"
+        );
+        let jsonl = render(&records, &fields, ListFormat::Jsonl, 59).unwrap();
+        let first = jsonl.lines().next().unwrap();
+        assert_eq!(
+            first,
+            r#"{"id":1,"user":"alice, \"work\"","code":"287082","note":null}"#
+        );
+        let json: Value =
+            serde_json::from_str(&render(&records, &fields, ListFormat::Json, 59).unwrap())
+                .unwrap();
+        assert_eq!(json[1]["code"], Value::Null);
+    }
+
+    #[test]
+    fn sort_records() {
+        let mut records = records();
+        sort(&mut records, Field::Account, 0).unwrap();
+        assert_eq!(records[0].account, Some("bank".to_string()));
+        // Missing values sort last
+        sort(&mut records, Field::User, 0).unwrap();
+        assert_eq!(records[0].id, 1);
+        sort(&mut records, Field::Id, 0).unwrap();
+        assert_eq!(records[0].id, 1);
+    }
+}
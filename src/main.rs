@@ -2,8 +2,12 @@ extern crate core;
 
 use std::io::Write;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 
+use crate::config::Config;
+use crate::db::backup::{Backup, Conflict, Outcome};
 use crate::db::encryption::Encryption;
+use crate::db::kdf::{Key, Keyfile};
 use crate::db::models::record::Record;
 use crate::db::Db;
 use crate::errors::TotpError;
@@ -14,13 +18,22 @@ use chrono::{DateTime, FixedOffset};
 use clap::{Parser, Subcommand, ValueEnum};
 use db::storage::StorageTrait;
 use env_logger::Env;
+use import::ImportFormat;
+use list::{Field, ListFormat};
 use otp::generator::Generator;
-use otp::token::Token;
+use otp::otpauth::OtpAuthUri;
+use otp::token::{Algorithm, OtpType, Token};
 use rpassword::read_password;
+use serde_json::json;
 
+#[cfg(unix)]
+mod agent;
 mod api;
+mod config;
 mod db;
 mod errors;
+mod import;
+mod list;
 mod otp;
 mod ui;
 
@@ -29,19 +42,47 @@ mod ui;
 #[clap(author, version, about, long_about = None)]
 struct Cli {
     /// The encryption password
-    #[clap(short, long)]
+    #[clap(short, long, group = "password_source")]
     password: Option<String>,
+    /// Read the password from this environment variable
+    #[clap(long, value_name = "VAR", group = "password_source")]
+    password_env: Option<String>,
+    /// Read the password from the first line of this file
+    #[clap(long, value_name = "PATH", group = "password_source")]
+    password_file: Option<PathBuf>,
+    /// Run this shell command and use its output as the password
+    #[clap(long, value_name = "COMMAND", group = "password_source")]
+    password_command: Option<String>,
+    /// A file required alongside the password, for vaults protected by a keyfile
+    #[clap(long)]
+    keyfile: Option<PathBuf>,
     /// The sqlite filename
     #[clap(short, long, default_value = ".totp.sqlite3")]
     sqlite_path: String,
     /// Automatically set the table lock key
     #[clap(short, long)]
     auto_lock_key: bool,
+    /// The agent's socket, defaults to $XDG_RUNTIME_DIR/trotp/agent.sock
+    #[cfg(unix)]
+    #[clap(long)]
+    agent_socket: Option<PathBuf>,
+    /// The config file, defaults to $XDG_CONFIG_HOME/trotp/config.toml
+    #[clap(long)]
+    config: Option<PathBuf>,
     /// Commands
     #[clap(subcommand)]
     command: Option<Commands>,
 }
 
+impl Cli {
+    fn has_password_source(&self) -> bool {
+        self.password.is_some()
+            || self.password_env.is_some()
+            || self.password_file.is_some()
+            || self.password_command.is_some()
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum OutputFormat {
     /// Json
@@ -55,8 +96,8 @@ enum Commands {
     /// Add a new account
     Add {
         /// Account name
-        #[clap(short, long)]
-        account: String,
+        #[clap(short, long, required_unless_present = "uri")]
+        account: Option<String>,
 
         /// User ID
         #[clap(short, long)]
@@ -75,16 +116,34 @@ enum Commands {
         secret: Option<Token>,
 
         /// Digits
-        #[clap(short, long, default_value = "6")]
+        #[clap(short, long, default_value = "6", conflicts_with = "uri")]
         digits: usize,
 
         /// Skew
-        #[clap(short = 'k', long, default_value = "1")]
+        #[clap(short = 'k', long, default_value = "1", conflicts_with = "uri")]
         skew: u8,
 
         /// Step
-        #[clap(short = 't', long, default_value = "30")]
+        #[clap(short = 't', long, default_value = "30", conflicts_with = "uri")]
         step: u64,
+
+        /// Algorithm
+        #[clap(long, default_value = "sha1", conflicts_with = "uri")]
+        #[arg(value_enum)]
+        algorithm: Algorithm,
+
+        /// OTP Type
+        #[clap(long, default_value = "totp", conflicts_with = "uri")]
+        #[arg(value_enum)]
+        otp_type: OtpType,
+
+        /// Initial HOTP counter
+        #[clap(short, long, default_value = "0", conflicts_with = "uri")]
+        counter: u64,
+
+        /// An otpauth:// URI to take the account, user, secret and parameters from
+        #[clap(long, conflicts_with = "secret")]
+        uri: Option<OtpAuthUri>,
     },
     /// Edit an existing account
     Edit {
@@ -108,9 +167,30 @@ enum Commands {
         #[clap(short, long)]
         password: Option<String>,
 
-        /// TOTP Secret
+        /// TOTP Secret, the account keeps its other parameters
         #[clap(short, long)]
         secret: Option<Token>,
+
+        /// Digits, 6 to 8
+        #[clap(short, long)]
+        digits: Option<usize>,
+
+        /// Skew
+        #[clap(short = 'k', long)]
+        skew: Option<u8>,
+
+        /// Step
+        #[clap(short = 't', long)]
+        step: Option<u64>,
+
+        /// Algorithm
+        #[clap(long)]
+        #[arg(value_enum)]
+        algorithm: Option<Algorithm>,
+
+        /// Show the changes without saving them
+        #[clap(long)]
+        dry_run: bool,
     },
     /// Delete an account
     Delete {
@@ -134,6 +214,10 @@ enum Commands {
         /// Range in minutes (applies before and after the start time)
         #[clap(short, long, default_value = "1")]
         range: u64,
+        /// Algorithm
+        #[clap(long, default_value = "sha1")]
+        #[arg(value_enum)]
+        algorithm: Algorithm,
     },
     /// Dump the config file
     Dump {
@@ -142,6 +226,169 @@ enum Commands {
         #[arg(value_enum)]
         format: OutputFormat,
     },
+    /// List accounts with their current codes, secrets stay hidden unless revealed
+    List {
+        /// Output format
+        #[clap(short, long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+        /// Comma separated fields to show [default: id,account,user,code,expiry]
+        #[clap(long, value_enum, value_delimiter = ',')]
+        fields: Vec<Field>,
+        /// Sort by this field
+        #[clap(short, long, value_enum, default_value_t = Field::Id)]
+        sort: Field,
+        /// Reverse the sort order
+        #[clap(short, long)]
+        reverse: bool,
+        /// Allow the secret and password fields, which are added to the default fields
+        #[clap(long)]
+        reveal: bool,
+    },
+    /// Print the current code of an account
+    Code {
+        /// The account id or name, exact names win over partial matches
+        account: String,
+        /// Print the account name and expiry as JSON
+        #[clap(short, long)]
+        json: bool,
+        /// Wait for the next code when the current one expires in fewer than this many seconds
+        #[clap(short, long, value_name = "SECONDS")]
+        wait_min_validity: Option<u64>,
+    },
+    /// Copy the current code of an account, waiting to clear it from the clipboard again
+    Copy {
+        /// The account id or name, exact names win over partial matches
+        account: String,
+        /// Seconds before the clipboard is cleared, 0 leaves it, defaults to the config's clear_after
+        #[clap(short = 't', long, value_name = "SECONDS")]
+        clear_after: Option<u64>,
+    },
+    /// Generate the next code for an HOTP account and advance its counter
+    Hotp {
+        /// Id
+        #[clap(short, long)]
+        id: u32,
+    },
+    /// Resynchronise an HOTP account's counter from a code
+    Resync {
+        /// Id
+        #[clap(short, long)]
+        id: u32,
+        /// The code shown by the token
+        #[clap(short, long)]
+        otp: String,
+        /// How many counters ahead of the stored one to search
+        #[clap(short, long, default_value = "100")]
+        window: u64,
+    },
+    /// Export a record as an otpauth:// URI
+    ExportUri {
+        /// Id
+        #[clap(short, long)]
+        id: u32,
+    },
+    /// Show a record's otpauth:// URI as a QR code
+    Qr {
+        /// Id
+        #[clap(short, long)]
+        id: u32,
+        /// Write the QR code to a .png or .svg file instead of the terminal
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// Draw dark modules on a light background, for light terminal themes
+        #[clap(long)]
+        invert: bool,
+    },
+    /// Import accounts from QR codes in PNG or JPEG images
+    ImportQr {
+        /// Image files containing otpauth:// or otpauth-migration:// QR codes
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+        /// Show the accounts that would be created without saving them
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Import accounts from otpauth:// or otpauth-migration:// URIs
+    ImportUri {
+        /// The URIs to import
+        #[clap(required = true)]
+        uris: Vec<String>,
+        /// Show the accounts that would be created without saving them
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Import accounts from another authenticator app's backup file
+    Import {
+        /// The backup file
+        file: PathBuf,
+        /// The app the backup was exported from
+        #[clap(short, long)]
+        #[arg(value_enum)]
+        format: ImportFormat,
+        /// Password for an encrypted Aegis vault, prompted for when required
+        #[clap(short, long)]
+        password: Option<String>,
+        /// Show the accounts that would be created without saving them
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Export accounts as Google Authenticator otpauth-migration:// URIs
+    ExportMigration {
+        /// Ids of the accounts to export, all accounts when omitted
+        #[clap(short, long)]
+        id: Vec<u32>,
+        /// Accounts per URI
+        #[clap(short, long, default_value_t = otp::migration::DEFAULT_BATCH_SIZE)]
+        batch_size: usize,
+        /// Print each batch as a QR code instead of a URI
+        #[clap(long)]
+        qr: bool,
+        /// Write each batch to a .png or .svg QR code file, numbered when there are several
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Change the vault password, re-encrypting every account
+    Passwd {
+        /// The new password, prompted for twice when omitted
+        #[clap(short, long)]
+        new_password: Option<String>,
+    },
+    /// Require a keyfile alongside the password, re-encrypting every account
+    AddKeyfile {
+        /// The keyfile, any file whose contents will not change
+        file: PathBuf,
+        /// Create the file with random contents, it must not exist yet
+        #[clap(short, long)]
+        generate: bool,
+    },
+    /// Stop requiring a keyfile, re-encrypting every account
+    RemoveKeyfile,
+    /// Write every account to an encrypted backup that opens with its own password
+    Backup {
+        /// The backup file
+        #[clap(short, long)]
+        out: PathBuf,
+        /// The backup password, prompted for twice when omitted
+        #[clap(short, long)]
+        password: Option<String>,
+    },
+    /// Restore accounts from a backup, merging them into the vault unless replacing it
+    Restore {
+        /// The backup file
+        file: PathBuf,
+        /// The backup password, prompted for when omitted
+        #[clap(short, long)]
+        password: Option<String>,
+        /// Remove every account first, restoring the backup as it was
+        #[clap(long)]
+        replace: bool,
+        /// How to merge an account whose name matches an account with another secret
+        #[clap(long, value_enum, default_value_t = Conflict::Skip, conflicts_with = "replace")]
+        on_conflict: Conflict,
+        /// Show what would be restored without saving it
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+    },
     /// Extract the TOTP Secret from a record
     Secret {
         /// Id
@@ -154,22 +401,62 @@ enum Commands {
         #[clap(short, long, default_value = "0.0.0.0:8080")]
         listen: SocketAddr,
     },
+    /// Hold unlocked vault keys in memory so other commands do not ask for the password
+    #[cfg(unix)]
+    Agent {
+        /// Lock every vault after this many seconds without use, 0 never locks
+        #[clap(short, long, default_value_t = agent::DEFAULT_TIMEOUT)]
+        timeout: u64,
+    },
+    /// Unlock the vault in the running agent
+    #[cfg(unix)]
+    Unlock,
+    /// Make the running agent forget every unlocked vault
+    #[cfg(unix)]
+    Lock,
 }
 
 fn main() -> Result<(), TotpError> {
     env_logger::Builder::from_env(Env::default().default_filter_or("trotp=info")).init();
     let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref())?;
 
-    let password = match cli.password {
-        Some(password) => password,
-        None => {
+    // The agent commands run without opening a vault
+    #[cfg(unix)]
+    match &cli.command {
+        Some(Commands::Agent { timeout }) => {
+            let timeout = Some(*timeout)
+                .filter(|t| *t > 0)
+                .map(std::time::Duration::from_secs);
+            return agent::server::Agent::new(agent_socket(&cli), timeout).start();
+        }
+        Some(Commands::Lock) => {
+            agent::client::Client::new(agent_socket(&cli)).lock(None)?;
+            println!("Locked");
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let agent_key = agent_key(&cli);
+    let unlocked_by_agent = agent_key.is_some();
+    let password = match (password_from_source(&cli)?, &agent_key) {
+        (Some(password), _) => password,
+        // The agent already holds the derived key
+        (None, Some(_)) => String::new(),
+        (None, None) => {
             print!("Password: ");
             std::io::stdout().flush().unwrap();
             read_password().unwrap()
         }
     };
 
-    let db = Db::new(password, Some(cli.sqlite_path))?;
+    let mut db = Db::new(password, Some(cli.sqlite_path.clone()))?;
+    if let Some(key) = agent_key {
+        db.set_key(key);
+    } else if let Some(keyfile) = &cli.keyfile {
+        db.set_keyfile(Some(Keyfile::read(keyfile)?));
+    }
     db.init()?;
     let mut storage = db::storage::sqlite::SqliteStorage::new(db, Encryption::default());
     storage.load()?;
@@ -183,10 +470,20 @@ fn main() -> Result<(), TotpError> {
                 return Err(TotpError::MissingLockKey);
             }
         }
+        Err(TotpError::Decryption(_)) if unlocked_by_agent => {
+            return Err(TotpError::Agent(
+                "The agent's key no longer opens the vault, run trotp unlock".to_string(),
+            ));
+        }
         Err(e) => {
             return Err(e);
         }
     }
+    // Vaults are upgraded when they are unlocked with the password, not with the agent's key
+    if !unlocked_by_agent && storage.upgrade_encryption()? {
+        forget_agent_key(&cli);
+        log::info!("Upgraded the vault encryption");
+    }
     let command = match &cli.command {
         Some(command) => command,
         None => &Commands::Interactive {},
@@ -201,22 +498,42 @@ fn main() -> Result<(), TotpError> {
             digits,
             skew,
             step,
+            algorithm,
+            otp_type,
+            counter,
+            uri,
         } => {
             let token = secret.as_ref().map(|secret| Token {
                 secret: secret.secret.clone(),
                 digits: *digits,
                 skew: *skew,
                 step: *step,
+                algorithm: *algorithm,
+                otp_type: *otp_type,
             });
 
-            let record = Record {
-                account: Some(account.to_string()),
-                token,
-                password: password.clone(),
-                note: note.clone(),
-                user: user.clone(),
-                ..Record::default()
+            let record = match uri.clone().map(Record::from) {
+                // An explicit account and user take precedence, the token parameters conflict with --uri
+                Some(record) => Record {
+                    account: account.clone().or(record.account),
+                    user: user.clone().or(record.user),
+                    password: password.clone(),
+                    note: note.clone(),
+                    ..record
+                },
+                None => Record {
+                    account: account.clone(),
+                    token,
+                    password: password.clone(),
+                    note: note.clone(),
+                    user: user.clone(),
+                    counter: *counter,
+                    ..Record::default()
+                },
             };
+            if let Some(token) = &record.token {
+                token.validate()?;
+            }
             storage.add_account(record)?;
         }
         Commands::Edit {
@@ -226,8 +543,14 @@ fn main() -> Result<(), TotpError> {
             note,
             password,
             secret,
+            digits,
+            skew,
+            step,
+            algorithm,
+            dry_run,
         } => {
-            let mut record = storage.get_account(*id)?;
+            let current = storage.get_account(*id)?;
+            let mut record = current.clone();
             record.account = account.clone().or(record.account);
             record.user = user.clone().or(record.user);
             record.note = note.clone().or(record.note);
@@ -240,11 +563,342 @@ fn main() -> Result<(), TotpError> {
                 (Some(token), _) => Some(token),
                 (_, Some(secret)) => Some(secret.clone()),
                 _ => None,
-            };
-
+            }
+            .map(|token| Token {
+                digits: digits.unwrap_or(token.digits),
+                skew: skew.unwrap_or(token.skew),
+                step: step.unwrap_or(token.step),
+                algorithm: algorithm.unwrap_or(token.algorithm),
+                ..token
+            });
+            match &token {
+                Some(token) => token.validate()?,
+                None if digits.is_some()
+                    || skew.is_some()
+                    || step.is_some()
+                    || algorithm.is_some() =>
+                {
+                    return Err(TotpError::InvalidToken(format!(
+                        "id {} has no secret, pass --secret as well",
+                        id
+                    )));
+                }
+                None => {}
+            }
             record.token = token;
-            eprintln!("record = {:?}", record);
-            storage.edit_account(record)?;
+
+            let diff = current.diff(&record);
+            if diff.is_empty() {
+                println!("Nothing to change");
+                return Ok(());
+            }
+            for line in diff {
+                println!("{}", line);
+            }
+            if !*dry_run {
+                storage.edit_account(record)?;
+            }
+        }
+        Commands::List {
+            format,
+            fields,
+            sort,
+            reverse,
+            reveal,
+        } => {
+            let fields = list::fields(fields, *reveal)?;
+            // One timestamp so every code and expiry is from the same moment
+            let now = chrono::Utc::now().timestamp() as u64;
+            let mut records = storage.accounts()?;
+            list::sort(&mut records, *sort, now)?;
+            if *reverse {
+                records.reverse();
+            }
+            let output = list::render(&records, &fields, *format, now)?;
+            // Stop quietly when piped into something like head that exits early
+            let written = if output.is_empty() {
+                Ok(())
+            } else {
+                writeln!(std::io::stdout(), "{}", output)
+            };
+            match written {
+                Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Commands::Code {
+            account,
+            json,
+            wait_min_validity,
+        } => {
+            let record = storage.find_account(account)?;
+            let generator = record
+                .token
+                .clone()
+                .map(Generator::new)
+                .transpose()?
+                .ok_or_else(|| TotpError::AccountNotFound(format!("{} has no secret", account)))?;
+            let output = if generator.is_hotp() {
+                let counter = storage.increment_counter(record.id)?;
+                let code = generator.generate_hotp(counter);
+                json!({"account_name": record.account, "code": code, "counter": counter})
+            } else {
+                let (mut code, mut expiry) = generator.generate(None)?;
+                if expiry < wait_min_validity.unwrap_or_default() {
+                    std::thread::sleep(std::time::Duration::from_secs(expiry));
+                    (code, expiry) = generator.generate(None)?;
+                }
+                json!({"account_name": record.account, "code": code, "expiry": expiry})
+            };
+            if *json {
+                println!("{}", output);
+            } else {
+                println!("{}", output["code"].as_str().unwrap_or_default());
+            }
+        }
+        Commands::Copy {
+            account,
+            clear_after,
+        } => {
+            let record = storage.find_account(account)?;
+            let generator = record
+                .token
+                .clone()
+                .map(Generator::new)
+                .transpose()?
+                .ok_or_else(|| TotpError::AccountNotFound(format!("{} has no secret", account)))?;
+            let code = if generator.is_hotp() {
+                generator.generate_hotp(storage.increment_counter(record.id)?)
+            } else {
+                generator.generate(None)?.0
+            };
+            let mut clipboard = ui::clip::Clipboard::new(config.clipboard.backend);
+            clipboard.set(&code)?;
+            let name = record.account.unwrap_or_default();
+            let clear_after = clear_after.unwrap_or(config.clipboard.clear_after);
+            if clear_after == 0 {
+                println!("Copied the code for {}", name);
+                return Ok(());
+            }
+            println!(
+                "Copied the code for {}, clearing the clipboard in {}s",
+                name, clear_after
+            );
+            // Ctrl-c clears the clipboard early instead of leaving the code behind
+            let (sender, receiver) = std::sync::mpsc::channel();
+            ctrlc::set_handler(move || {
+                let _ = sender.send(());
+            })
+            .map_err(|e| TotpError::ClipboardError(e.to_string()))?;
+            let _ = receiver.recv_timeout(std::time::Duration::from_secs(clear_after));
+            if clipboard.clear(&code)? {
+                println!("Clipboard cleared");
+            } else {
+                println!("The clipboard has changed, leaving it as it is");
+            }
+        }
+        Commands::Hotp { id } => {
+            let generator = hotp_generator(&storage.get_account(*id)?)?;
+            let counter = storage.increment_counter(*id)?;
+            println!("{}", generator.generate_hotp(counter));
+        }
+        Commands::Resync { id, otp, window } => {
+            let record = storage.get_account(*id)?;
+            let generator = hotp_generator(&record)?;
+            let counter = generator
+                .resync(otp, record.counter, *window)
+                .ok_or(TotpError::InvalidOtpForWindow)?;
+            // The matched code has been used, continue from the one after it
+            storage.set_counter(*id, counter + 1)?;
+            println!("Counter resynchronised to {}", counter + 1);
+        }
+        Commands::ExportUri { id } => {
+            let record = storage.get_account(*id)?;
+            println!("{}", OtpAuthUri::from_record(&record)?);
+        }
+        Commands::Qr { id, output, invert } => {
+            let record = storage.get_account(*id)?;
+            let uri = OtpAuthUri::from_record(&record)?.to_string();
+            match output {
+                Some(path) => otp::qr::write_file(&uri, path)?,
+                None => println!("{}", otp::qr::render_unicode(&uri, *invert)?),
+            }
+        }
+        Commands::ImportQr { files, dry_run } => {
+            let mut uris = vec![];
+            for file in files {
+                let contents = otp::qr::decode_file(file)?;
+                if contents.is_empty() {
+                    eprintln!("No QR codes found in {}", file.display());
+                }
+                uris.extend(contents);
+            }
+            import_uris(&mut storage, &uris, *dry_run)?;
+        }
+        Commands::ImportUri { uris, dry_run } => {
+            import_uris(&mut storage, uris, *dry_run)?;
+        }
+        Commands::Import {
+            file,
+            format,
+            password,
+            dry_run,
+        } => {
+            let contents = std::fs::read_to_string(file)?;
+            let password = match password {
+                None if *format == ImportFormat::Aegis
+                    && import::aegis::is_encrypted(&contents) =>
+                {
+                    print!("Aegis vault password: ");
+                    std::io::stdout().flush()?;
+                    Some(read_password()?)
+                }
+                password => password.clone(),
+            };
+            let records = import::parse(*format, &contents, password.as_deref())?;
+            import_records(&mut storage, records, *dry_run)?;
+        }
+        Commands::ExportMigration {
+            id,
+            batch_size,
+            qr,
+            output,
+        } => {
+            let records = if id.is_empty() {
+                storage.accounts()?
+            } else {
+                id.iter()
+                    .map(|id| storage.get_account(*id))
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            let uris = records
+                .iter()
+                .filter(|record| record.token.is_some())
+                .map(OtpAuthUri::from_record)
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .filter(|uri| match otp::migration::check_supported(uri) {
+                    Ok(()) => true,
+                    Err(TotpError::TotpUrl(e)) => {
+                        eprintln!("Skipping {}", e);
+                        false
+                    }
+                    Err(e) => {
+                        eprintln!("Skipping {:?}", e);
+                        false
+                    }
+                })
+                .collect::<Vec<_>>();
+            let batches = otp::migration::encode_uris(&uris, *batch_size)?;
+            if let Some(output) = output {
+                for path in write_migration_batches(&batches, output)? {
+                    println!("Wrote {}", path.display());
+                }
+            } else {
+                for (index, batch) in batches.iter().enumerate() {
+                    if *qr {
+                        println!("Batch {} of {}", index + 1, batches.len());
+                        println!("{}", otp::qr::render_unicode(batch, false)?);
+                    } else {
+                        println!("{}", batch);
+                    }
+                }
+            }
+        }
+        Commands::Passwd { new_password } => {
+            let new_password = match new_password {
+                Some(new_password) => new_password.clone(),
+                None => read_new_password("New password")?,
+            };
+            if new_password.is_empty() {
+                return Err(TotpError::Password(
+                    "The password cannot be empty".to_string(),
+                ));
+            }
+            storage.change_password(&new_password)?;
+            forget_agent_key(&cli);
+            println!("Password changed");
+        }
+        Commands::AddKeyfile { file, generate } => {
+            let keyfile = if *generate {
+                // Written before re-encrypting so the vault never depends on a file that failed to save
+                let keyfile = Keyfile::generate()?;
+                write_keyfile(file, &keyfile)?;
+                keyfile
+            } else {
+                Keyfile::read(file)?
+            };
+            storage.change_keyfile(Some(keyfile))?;
+            forget_agent_key(&cli);
+            println!(
+                "The vault now requires --keyfile {}, keep a backup of it",
+                file.display()
+            );
+        }
+        Commands::RemoveKeyfile => {
+            storage.change_keyfile(None)?;
+            forget_agent_key(&cli);
+            println!("The vault no longer requires a keyfile");
+        }
+        Commands::Backup { out, password } => {
+            let password = match password {
+                Some(password) => password.clone(),
+                None => read_new_password("Backup password")?,
+            };
+            if password.is_empty() {
+                return Err(TotpError::Password(
+                    "The backup password cannot be empty".to_string(),
+                ));
+            }
+            let records = storage.accounts()?;
+            let backup = Backup::seal(&records, &password)?;
+            write_private(out, serde_json::to_string_pretty(&backup)?.as_bytes())?;
+            println!("Backed up {} accounts to {}", records.len(), out.display());
+        }
+        Commands::Restore {
+            file,
+            password,
+            replace,
+            on_conflict,
+            dry_run,
+        } => {
+            let backup: Backup = serde_json::from_str(&std::fs::read_to_string(file)?)?;
+            let password = match password {
+                Some(password) => password.clone(),
+                None => {
+                    print!("Backup password: ");
+                    std::io::stdout().flush()?;
+                    read_password()?
+                }
+            };
+            let records = backup.open(&password)?;
+            let outcomes = if *replace {
+                records
+                    .into_iter()
+                    .map(|record| (Outcome::Added, record))
+                    .collect::<Vec<_>>()
+            } else {
+                db::backup::merge(&storage.accounts()?, records, *on_conflict)
+            };
+            for (outcome, record) in outcomes.iter() {
+                let action = match (outcome, *dry_run) {
+                    (Outcome::Added, false) => "Restored",
+                    (Outcome::Added, true) => "Would restore",
+                    (Outcome::Updated, false) => "Updated",
+                    (Outcome::Updated, true) => "Would update",
+                    (Outcome::Unchanged, _) => "Unchanged",
+                    (Outcome::Skipped, _) => "Skipped conflicting",
+                };
+                println!("{} {}", action, import_summary(record));
+            }
+            if !*dry_run {
+                let records = outcomes
+                    .into_iter()
+                    .filter(|(outcome, _)| matches!(outcome, Outcome::Added | Outcome::Updated))
+                    .map(|(_, record)| record)
+                    .collect();
+                storage.restore_accounts(records, *replace)?;
+            }
         }
         Commands::Secret { id } => {
             let record = storage.get_account(*id)?;
@@ -262,8 +916,12 @@ fn main() -> Result<(), TotpError> {
             otp,
             start,
             range,
+            algorithm,
         } => {
-            let generator = Generator::new(token.to_owned())?;
+            let generator = Generator::new(Token {
+                algorithm: *algorithm,
+                ..token.to_owned()
+            })?;
             let output = generator.check_range(otp, start.timestamp() as u64, *range)?;
             let local_date = DateTime::<FixedOffset>::from_utc(output, *start.offset());
             println!(
@@ -342,11 +1000,369 @@ fn main() -> Result<(), TotpError> {
             }
         }
         Commands::Interactive => {
-            ui::init(storage)?;
+            ui::init(storage, &config)?;
         }
         Commands::Serve { listen } => {
             api::server::Server::new(*listen, storage)?.start()?;
         }
+        #[cfg(unix)]
+        Commands::Unlock => {
+            agent::client::Client::new(agent_socket(&cli))
+                .unlock(&agent::vault_id(&cli.sqlite_path), storage.db.key()?)?;
+            println!("Unlocked {} in the agent", cli.sqlite_path);
+        }
+        // Handled before the vault is opened
+        #[cfg(unix)]
+        Commands::Agent { .. } | Commands::Lock => {}
+    }
+    Ok(())
+}
+
+/// The password from `-p` or one of the non-interactive sources, `None` to prompt for it
+///
+/// Errors never include the password itself.
+fn password_from_source(cli: &Cli) -> Result<Option<String>, TotpError> {
+    let (password, source) = if let Some(password) = &cli.password {
+        return Ok(Some(password.clone()));
+    } else if let Some(var) = &cli.password_env {
+        let password = std::env::var(var)
+            .map_err(|_| TotpError::Password(format!("Environment variable {} is not set", var)))?;
+        (password, format!("Environment variable {}", var))
+    } else if let Some(path) = &cli.password_file {
+        let password = std::fs::read_to_string(path)
+            .map_err(|e| TotpError::Password(format!("Cannot read {}: {}", path.display(), e)))?;
+        let password = password.lines().next().unwrap_or_default().to_string();
+        (password, format!("Password file {}", path.display()))
+    } else if let Some(command) = &cli.password_command {
+        (
+            run_password_command(command)?,
+            "The password command".to_string(),
+        )
+    } else {
+        return Ok(None);
+    };
+    if password.is_empty() {
+        return Err(TotpError::Password(format!("{} is empty", source)));
+    }
+    Ok(Some(password))
+}
+
+fn run_password_command(command: &str) -> Result<String, TotpError> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = std::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let output = shell
+        .arg(command)
+        .stdin(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|e| TotpError::Password(format!("Cannot run the password command: {}", e)))?;
+    if !output.status.success() {
+        return Err(TotpError::Password(format!(
+            "The password command failed with {}",
+            output.status
+        )));
+    }
+    let output = String::from_utf8(output.stdout).map_err(|_| {
+        TotpError::Password("The password command printed invalid UTF-8".to_string())
+    })?;
+    Ok(output.lines().next().unwrap_or_default().to_string())
+}
+
+#[cfg(unix)]
+fn agent_socket(cli: &Cli) -> PathBuf {
+    cli.agent_socket
+        .clone()
+        .unwrap_or_else(agent::default_socket)
+}
+
+/// The vault's key from a running agent, unless the command needs the password itself
+#[cfg(unix)]
+fn agent_key(cli: &Cli) -> Option<Key> {
+    if cli.has_password_source()
+        || matches!(
+            cli.command,
+            Some(
+                Commands::Unlock
+                    | Commands::Passwd { .. }
+                    | Commands::AddKeyfile { .. }
+                    | Commands::RemoveKeyfile
+            )
+        )
+    {
+        return None;
+    }
+    agent::client::Client::new(agent_socket(cli))
+        .key(&agent::vault_id(&cli.sqlite_path))
+        .unwrap_or_else(|e| {
+            log::debug!("Not using the agent: {:?}", e);
+            None
+        })
+}
+
+#[cfg(not(unix))]
+fn agent_key(_cli: &Cli) -> Option<Key> {
+    None
+}
+
+/// A re-keyed vault no longer opens with the key the agent holds
+#[cfg(unix)]
+fn forget_agent_key(cli: &Cli) {
+    let _ = agent::client::Client::new(agent_socket(cli))
+        .lock(Some(&agent::vault_id(&cli.sqlite_path)));
+}
+
+#[cfg(not(unix))]
+fn forget_agent_key(_cli: &Cli) {}
+
+fn hotp_generator(record: &Record) -> Result<Generator, TotpError> {
+    let generator = record
+        .token
+        .clone()
+        .map(Generator::new)
+        .transpose()?
+        .filter(|generator| generator.is_hotp());
+    generator.ok_or_else(|| TotpError::NotHotp(format!("id {} is not an HOTP account", record.id)))
+}
+
+fn import_uris<T: StorageTrait>(
+    storage: &mut T,
+    uris: &[String],
+    dry_run: bool,
+) -> Result<(), TotpError> {
+    let mut records = vec![];
+    for uri in uris {
+        records.extend(OtpAuthUri::parse_all(uri)?.into_iter().map(Record::from));
+    }
+    import_records(storage, records, dry_run)
+}
+
+fn import_records<T: StorageTrait>(
+    storage: &mut T,
+    records: Vec<Record>,
+    dry_run: bool,
+) -> Result<(), TotpError> {
+    // Check every record first so a bad entry does not leave the import half done
+    for record in &records {
+        if let Some(Err(TotpError::InvalidToken(e))) = record.token.as_ref().map(Token::validate) {
+            return Err(TotpError::InvalidToken(format!(
+                "{}: {}",
+                import_summary(record),
+                e
+            )));
+        }
+    }
+    let action = if dry_run { "Would add" } else { "Added" };
+    for record in records {
+        let summary = import_summary(&record);
+        if !dry_run {
+            storage.add_account(record)?;
+        }
+        println!("{} {}", action, summary);
     }
     Ok(())
 }
+
+/// Prompt for a new password twice
+fn read_new_password(prompt: &str) -> Result<String, TotpError> {
+    print!("{}: ", prompt);
+    std::io::stdout().flush()?;
+    let password = read_password()?;
+    print!("Confirm {}: ", prompt.to_lowercase());
+    std::io::stdout().flush()?;
+    if read_password()? != password {
+        return Err(TotpError::Password("Passwords do not match".to_string()));
+    }
+    Ok(password)
+}
+
+/// Write a file only the current user can read
+fn write_private(path: &std::path::Path, contents: &[u8]) -> Result<(), TotpError> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|e| TotpError::Backup(format!("Cannot write {}: {}", path.display(), e)))
+}
+
+fn write_keyfile(path: &std::path::Path, keyfile: &Keyfile) -> Result<(), TotpError> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(keyfile.as_bytes()))
+        .map_err(|e| {
+            TotpError::Password(format!("Cannot create keyfile {}: {}", path.display(), e))
+        })
+}
+
+/// Write each migration batch as a QR code image, numbering the files when there is more than one
+fn write_migration_batches(
+    batches: &[String],
+    output: &std::path::Path,
+) -> Result<Vec<PathBuf>, TotpError> {
+    batches
+        .iter()
+        .enumerate()
+        .map(|(index, batch)| {
+            let path = if batches.len() > 1 {
+                numbered_path(output, index + 1)
+            } else {
+                output.to_path_buf()
+            };
+            otp::qr::write_file(batch, &path)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+fn numbered_path(path: &std::path::Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, index, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, index),
+    };
+    path.with_file_name(name)
+}
+
+fn import_summary(record: &Record) -> String {
+    let mut summary = record.account.clone().unwrap_or_default();
+    if let Some(user) = record.user.as_ref() {
+        summary.push_str(&format!(" ({})", user));
+    }
+    if let Some(token) = record.token.as_ref() {
+        summary.push_str(&format!(
+            " [{} {} {} digits]",
+            token.otp_type, token.algorithm, token.digits
+        ));
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "correct horse battery staple";
+
+    fn password(args: &[&str]) -> Result<Option<String>, TotpError> {
+        let cli = Cli::try_parse_from([&["trotp"], args].concat()).unwrap();
+        password_from_source(&cli)
+    }
+
+    /// The password is never repeated in an error, it could end up in logs or a terminal
+    fn assert_error(result: Result<Option<String>, TotpError>) {
+        match result {
+            Err(TotpError::Password(message)) => assert!(!message.contains(SECRET), "{}", message),
+            other => panic!("Expected a password error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn password_from_env() {
+        std::env::set_var("TROTP_TEST_PASSWORD", SECRET);
+        std::env::set_var("TROTP_TEST_EMPTY_PASSWORD", "");
+        assert_eq!(
+            password(&["--password-env", "TROTP_TEST_PASSWORD"]).unwrap(),
+            Some(SECRET.to_string())
+        );
+        assert_error(password(&["--password-env", "TROTP_TEST_EMPTY_PASSWORD"]));
+        assert_error(password(&["--password-env", "TROTP_TEST_MISSING_PASSWORD"]));
+        assert_eq!(password(&[]).unwrap(), None);
+        assert_eq!(password(&["-p", SECRET]).unwrap(), Some(SECRET.to_string()));
+    }
+
+    #[test]
+    fn password_from_file() {
+        let dir = std::env::temp_dir().join(format!("trotp-password-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("password");
+        let file_arg = file.to_str().unwrap();
+        // Only the first line is used, so a trailing newline or comment does not matter
+        std::fs::write(&file, format!("{}\nsecond line\n", SECRET)).unwrap();
+        assert_eq!(
+            password(&["--password-file", file_arg]).unwrap(),
+            Some(SECRET.to_string())
+        );
+        std::fs::write(&file, "").unwrap();
+        assert_error(password(&["--password-file", file_arg]));
+        std::fs::write(&file, "\nsecond line\n").unwrap();
+        assert_error(password(&["--password-file", file_arg]));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_error(password(&["--password-file", file_arg]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn password_from_command() {
+        let command = format!("echo '{}'", SECRET);
+        assert_eq!(
+            password(&["--password-command", &command]).unwrap(),
+            Some(SECRET.to_string())
+        );
+        // A command that prints the password before failing does not leak it
+        let failing = format!("echo '{}'; exit 3", SECRET);
+        assert_error(password(&["--password-command", &failing]));
+        assert_error(password(&["--password-command", "true"]));
+        assert_error(password(&["--password-command", "trotp-no-such-command"]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn export_migration_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("trotp-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let uris = (0..3)
+            .map(|i| {
+                format!("otpauth://totp/Example{}?secret=JBSWY3DPEHPK3PXP", i)
+                    .parse::<OtpAuthUri>()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let batches = otp::migration::encode_uris(&uris, 2).unwrap();
+        let paths = write_migration_batches(&batches, &dir.join("export.png")).unwrap();
+        let modes = paths
+            .iter()
+            .map(|path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777)
+            .collect::<Vec<_>>();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            paths,
+            vec![dir.join("export-1.png"), dir.join("export-2.png")]
+        );
+        assert_eq!(modes, vec![0o600, 0o600]);
+    }
+
+    #[test]
+    fn add_uri_conflicts_with_token_parameters() {
+        let uri = "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&digits=8";
+        for flag in [
+            "--digits=6",
+            "--skew=2",
+            "--step=60",
+            "--algorithm=sha256",
+            "--otp-type=hotp",
+            "--counter=3",
+        ] {
+            let parsed = Cli::try_parse_from(["trotp", "add", "--uri", uri, flag]);
+            assert!(parsed.is_err(), "{}", flag);
+        }
+        let cli = Cli::try_parse_from(["trotp", "add", "--uri", uri, "--user", "bob"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Add { uri: Some(_), .. })
+        ));
+    }
+}
@@ -1,19 +1,22 @@
 use crate::errors::TotpError;
-use crate::otp::token::Token;
+use crate::otp::token::{OtpType, Token};
 use chrono::NaiveDateTime;
-use totp_rs::{Algorithm, TOTP};
+use totp_rs::TOTP;
 
 #[derive(Clone)]
 pub struct Generator {
     totp: TOTP<Token>,
     step: u64,
+    otp_type: OtpType,
 }
 
 impl Generator {
     pub fn new(token: Token) -> Result<Self, TotpError> {
+        token.validate()?;
         let step = token.step;
+        let otp_type = token.otp_type;
         let totp: TOTP<Token> = TOTP {
-            algorithm: Algorithm::SHA1,
+            algorithm: token.algorithm.into(),
             digits: token.digits,
             skew: token.skew,
             step,
@@ -21,7 +24,31 @@ impl Generator {
         };
 
         // let totp = TOTP::new(Algorithm::SHA1, token.digits, token.skew, step, token)?;
-        Ok(Self { totp, step })
+        Ok(Self {
+            totp,
+            step,
+            otp_type,
+        })
+    }
+
+    pub fn is_hotp(&self) -> bool {
+        self.otp_type == OtpType::Hotp
+    }
+
+    /// Generate the RFC 4226 code for a counter value
+    pub fn generate_hotp(&self, counter: u64) -> String {
+        // With a step of 1 the moving factor is the counter itself
+        let hotp = TOTP {
+            step: 1,
+            ..self.totp.clone()
+        };
+        hotp.generate(counter)
+    }
+
+    /// Search `look_ahead` counters from `counter` for `code`, returning the matching counter
+    pub fn resync(&self, code: &str, counter: u64, look_ahead: u64) -> Option<u64> {
+        (counter..=counter.saturating_add(look_ahead))
+            .find(|counter| self.generate_hotp(*counter) == code)
     }
 
     pub fn generate(&self, time: Option<u64>) -> Result<(String, u64), TotpError> {
@@ -32,6 +59,17 @@ impl Generator {
         Ok((self.totp.generate(time), rounded_up))
     }
 
+    /// The code for the period after the one containing `time`, with its seconds until it expires
+    pub fn generate_next(&self, time: Option<u64>) -> Result<(String, u64), TotpError> {
+        let time = time.unwrap_or(chrono::Local::now().timestamp() as u64);
+        let (code, expiry) = self.generate(Some(time + self.step))?;
+        Ok((code, expiry + self.step))
+    }
+
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
     pub fn check_range(
         &self,
         code: &str,
@@ -67,9 +105,9 @@ impl TryFrom<Token> for Generator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::otp::token::Algorithm;
     use chrono::Timelike;
     use std::str::FromStr;
-    use totp_rs::{Rfc6238, Secret};
 
     #[test]
     fn generate() {
@@ -80,6 +118,83 @@ mod tests {
         assert_eq!(token, "975361");
     }
 
+    #[test]
+    fn reject_invalid_token() {
+        let token = Token::from_str("JBSWY3DPEHPK3PXP").unwrap();
+        assert!(Generator::new(Token {
+            step: 0,
+            ..token.clone()
+        })
+        .is_err());
+        assert!(Generator::new(Token {
+            digits: 12,
+            ..token
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn generate_next() {
+        let generator = Generator::new(Token {
+            step: 60,
+            ..Token::from_str("JBSWY3DPEHPK3PXP").unwrap()
+        })
+        .unwrap();
+        let (next, expiry) = generator.generate_next(Some(1654258053)).unwrap();
+        assert_eq!(next, generator.generate(Some(1654258053 + 60)).unwrap().0);
+        assert_eq!(expiry, 27 + 60);
+        assert_ne!(next, generator.generate(Some(1654258053)).unwrap().0);
+    }
+
+    #[test]
+    fn generate_with_algorithm() {
+        // Test vectors from RFC 6238 Appendix B
+        let cases = [
+            (Algorithm::Sha1, "12345678901234567890", "94287082"),
+            (
+                Algorithm::Sha256,
+                "12345678901234567890123456789012",
+                "46119246",
+            ),
+            (
+                Algorithm::Sha512,
+                "1234567890123456789012345678901234567890123456789012345678901234",
+                "90693936",
+            ),
+        ];
+        for (algorithm, secret, expected) in cases {
+            let generator = Generator::new(Token {
+                secret: secret.as_bytes().to_vec(),
+                digits: 8,
+                algorithm,
+                ..Token::default()
+            })
+            .unwrap();
+            let (token, _) = generator.generate(Some(59)).unwrap();
+            assert_eq!(token, expected);
+        }
+    }
+
+    #[test]
+    fn generate_hotp() {
+        // Test vectors from RFC 4226 Appendix D
+        let generator = Generator::new(Token {
+            secret: b"12345678901234567890".to_vec(),
+            otp_type: OtpType::Hotp,
+            ..Token::default()
+        })
+        .unwrap();
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(generator.generate_hotp(counter as u64), *code);
+        }
+        assert_eq!(generator.resync("399871", 2, 10), Some(8));
+        assert_eq!(generator.resync("399871", 2, 5), None);
+    }
+
     #[test]
     fn check_range() {
         let secret = Token::from_str("JBSWY3DPEHPK3PXP").unwrap();
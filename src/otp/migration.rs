@@ -0,0 +1,374 @@
+use crate::otp::otpauth::OtpAuthUri;
+use crate::otp::token::{Algorithm, OtpType, Token};
+use crate::TotpError;
+use data_encoding::BASE64;
+use openssl::rand::rand_bytes;
+use prost::Message;
+
+pub const SCHEME: &str = "otpauth-migration://";
+/// Google Authenticator splits its transfer QR codes into batches of this many accounts
+pub const DEFAULT_BATCH_SIZE: usize = 10;
+
+/// The protobuf batch behind Google Authenticator's "Transfer accounts" QR codes
+#[derive(Clone, PartialEq, Message)]
+pub struct MigrationPayload {
+    #[prost(message, repeated, tag = "1")]
+    pub otp_parameters: Vec<OtpParameters>,
+    #[prost(int32, tag = "2")]
+    pub version: i32,
+    #[prost(int32, tag = "3")]
+    pub batch_size: i32,
+    #[prost(int32, tag = "4")]
+    pub batch_index: i32,
+    #[prost(int32, tag = "5")]
+    pub batch_id: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct OtpParameters {
+    #[prost(bytes = "vec", tag = "1")]
+    pub secret: Vec<u8>,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub issuer: String,
+    #[prost(enumeration = "MigrationAlgorithm", tag = "4")]
+    pub algorithm: i32,
+    #[prost(enumeration = "MigrationDigits", tag = "5")]
+    pub digits: i32,
+    #[prost(enumeration = "MigrationOtpType", tag = "6")]
+    pub otp_type: i32,
+    #[prost(int64, tag = "7")]
+    pub counter: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+pub enum MigrationAlgorithm {
+    Unspecified = 0,
+    Sha1 = 1,
+    Sha256 = 2,
+    Sha512 = 3,
+    Md5 = 4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+pub enum MigrationDigits {
+    Unspecified = 0,
+    Six = 1,
+    Eight = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+pub enum MigrationOtpType {
+    Unspecified = 0,
+    Hotp = 1,
+    Totp = 2,
+}
+
+impl TryFrom<OtpParameters> for OtpAuthUri {
+    type Error = TotpError;
+
+    fn try_from(parameters: OtpParameters) -> Result<Self, Self::Error> {
+        let algorithm = match MigrationAlgorithm::try_from(parameters.algorithm) {
+            Ok(MigrationAlgorithm::Sha1) => Algorithm::Sha1,
+            Ok(MigrationAlgorithm::Sha256) => Algorithm::Sha256,
+            Ok(MigrationAlgorithm::Sha512) => Algorithm::Sha512,
+            Ok(MigrationAlgorithm::Md5) => {
+                return Err(TotpError::InvalidAlgorithm("MD5".to_string()))
+            }
+            // Guessing would store an account that generates the wrong codes
+            Ok(MigrationAlgorithm::Unspecified) | Err(_) => {
+                return Err(TotpError::InvalidAlgorithm(format!(
+                    "unknown algorithm {}",
+                    parameters.algorithm
+                )))
+            }
+        };
+        let digits = match MigrationDigits::try_from(parameters.digits) {
+            Ok(MigrationDigits::Eight) => 8,
+            _ => 6,
+        };
+        let otp_type = match MigrationOtpType::try_from(parameters.otp_type) {
+            Ok(MigrationOtpType::Hotp) => OtpType::Hotp,
+            _ => OtpType::Totp,
+        };
+        // Names are usually stored as "Issuer:label" alongside the separate issuer
+        let issuer = Some(parameters.issuer).filter(|i| !i.is_empty());
+        let label = match (issuer.as_ref(), parameters.name.split_once(':')) {
+            (Some(issuer), Some((prefix, label))) if prefix == issuer => label.trim_start(),
+            _ => parameters.name.as_str(),
+        }
+        .to_string();
+
+        let token = Token {
+            secret: parameters.secret,
+            digits,
+            algorithm,
+            otp_type,
+            ..Token::default()
+        };
+        token.validate()?;
+        Ok(Self {
+            issuer,
+            label,
+            token,
+            counter: parameters.counter.max(0) as u64,
+        })
+    }
+}
+
+/// Check the app can generate the same codes, it only supports 6 or 8 digits and a 30 second period
+pub fn check_supported(uri: &OtpAuthUri) -> Result<(), TotpError> {
+    let name = match uri.issuer.as_ref() {
+        Some(issuer) => format!("{}:{}", issuer, uri.label),
+        None => uri.label.clone(),
+    };
+    if !matches!(uri.token.digits, 6 | 8) {
+        return Err(TotpError::TotpUrl(format!(
+            "{} has {} digits, only 6 or 8 can be exported",
+            name, uri.token.digits
+        )));
+    }
+    if uri.token.otp_type == OtpType::Totp && uri.token.step != 30 {
+        return Err(TotpError::TotpUrl(format!(
+            "{} has a {} second period, only 30 can be exported",
+            name, uri.token.step
+        )));
+    }
+    Ok(())
+}
+
+impl From<&OtpAuthUri> for OtpParameters {
+    fn from(uri: &OtpAuthUri) -> Self {
+        let algorithm = match uri.token.algorithm {
+            Algorithm::Sha1 => MigrationAlgorithm::Sha1,
+            Algorithm::Sha256 => MigrationAlgorithm::Sha256,
+            Algorithm::Sha512 => MigrationAlgorithm::Sha512,
+        };
+        let digits = match uri.token.digits {
+            8 => MigrationDigits::Eight,
+            _ => MigrationDigits::Six,
+        };
+        let otp_type = match uri.token.otp_type {
+            OtpType::Totp => MigrationOtpType::Totp,
+            OtpType::Hotp => MigrationOtpType::Hotp,
+        };
+        Self {
+            secret: uri.token.secret.clone(),
+            name: uri.label.clone(),
+            issuer: uri.issuer.clone().unwrap_or_default(),
+            algorithm: algorithm as i32,
+            digits: digits as i32,
+            otp_type: otp_type as i32,
+            counter: uri.counter as i64,
+        }
+    }
+}
+
+/// Encode accounts into one `otpauth-migration://` URI per batch of `batch_size` accounts
+///
+/// Fails when an account would generate different codes in the app, see `check_supported`.
+pub fn encode_uris(uris: &[OtpAuthUri], batch_size: usize) -> Result<Vec<String>, TotpError> {
+    uris.iter().try_for_each(check_supported)?;
+    let batches = uris.chunks(batch_size.max(1)).collect::<Vec<_>>();
+    let mut batch_id = [0u8; 4];
+    rand_bytes(&mut batch_id)?;
+    // The app rejects negative batch ids
+    let batch_id = i32::from_be_bytes(batch_id) & i32::MAX;
+    Ok(batches
+        .iter()
+        .enumerate()
+        .map(|(batch_index, batch)| {
+            let payload = MigrationPayload {
+                otp_parameters: batch.iter().map(OtpParameters::from).collect(),
+                version: 1,
+                batch_size: batches.len() as i32,
+                batch_index: batch_index as i32,
+                batch_id,
+            };
+            let data = BASE64.encode(&payload.encode_to_vec());
+            format!("{}offline?data={}", SCHEME, urlencoding::encode(&data))
+        })
+        .collect())
+}
+
+/// Decode every account held in an `otpauth-migration://offline?data=...` URI
+///
+/// Accounts using an algorithm trotp cannot generate, such as MD5, are skipped with a warning.
+pub fn decode_uri(uri: &str) -> Result<Vec<OtpAuthUri>, TotpError> {
+    let uri = uri.trim();
+    let query = uri
+        .get(..SCHEME.len())
+        .filter(|scheme| scheme.eq_ignore_ascii_case(SCHEME))
+        .and_then(|_| uri.split_once('?'))
+        .map(|(_, query)| query)
+        .ok_or_else(|| TotpError::TotpUrl(format!("Expected {} URI", SCHEME)))?;
+    let data = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("data="))
+        .ok_or_else(|| TotpError::TotpUrl("Missing migration data".to_string()))?;
+    let data = urlencoding::decode(data).map_err(|e| TotpError::Utf8(e.to_string()))?;
+    // Some exporters drop the padding, which the strict decoder requires
+    let mut data = data.replace(' ', "+");
+    while data.len() % 4 != 0 {
+        data.push('=');
+    }
+    let payload = MigrationPayload::decode(BASE64.decode(data.as_bytes())?.as_slice())
+        .map_err(|e| TotpError::TotpUrl(e.to_string()))?;
+    let mut uris = vec![];
+    for parameters in payload.otp_parameters {
+        let name = parameters.name.clone();
+        match OtpAuthUri::try_from(parameters) {
+            Ok(uri) => uris.push(uri),
+            Err(TotpError::InvalidAlgorithm(algorithm)) => {
+                log::warn!("Skipping {}, {} is not supported", name, algorithm)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(uris)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload_uri(payload: &MigrationPayload) -> String {
+        let data = BASE64.encode(&payload.encode_to_vec());
+        format!(
+            "otpauth-migration://offline?data={}",
+            urlencoding::encode(&data)
+        )
+    }
+
+    #[test]
+    fn decode_payload() {
+        let payload = MigrationPayload {
+            otp_parameters: vec![
+                OtpParameters {
+                    secret: b"Hello!\xde\xad\xbe\xef".to_vec(),
+                    name: "ACME Co:john@example.com".to_string(),
+                    issuer: "ACME Co".to_string(),
+                    algorithm: MigrationAlgorithm::Sha256 as i32,
+                    digits: MigrationDigits::Eight as i32,
+                    otp_type: MigrationOtpType::Totp as i32,
+                    counter: 0,
+                },
+                OtpParameters {
+                    secret: b"12345678901234567890".to_vec(),
+                    name: "VPN".to_string(),
+                    issuer: String::new(),
+                    algorithm: MigrationAlgorithm::Sha1 as i32,
+                    digits: MigrationDigits::Six as i32,
+                    otp_type: MigrationOtpType::Hotp as i32,
+                    counter: 7,
+                },
+            ],
+            version: 1,
+            batch_size: 1,
+            batch_index: 0,
+            batch_id: 1,
+        };
+        let uris = decode_uri(&payload_uri(&payload)).unwrap();
+        assert_eq!(uris.len(), 2);
+        assert_eq!(uris[0].issuer, Some("ACME Co".to_string()));
+        assert_eq!(uris[0].label, "john@example.com");
+        assert_eq!(uris[0].token.secret, b"Hello!\xde\xad\xbe\xef".to_vec());
+        assert_eq!(uris[0].token.algorithm, Algorithm::Sha256);
+        assert_eq!(uris[0].token.digits, 8);
+        assert_eq!(uris[1].issuer, None);
+        assert_eq!(uris[1].label, "VPN");
+        assert_eq!(uris[1].token.otp_type, OtpType::Hotp);
+        assert_eq!(uris[1].counter, 7);
+    }
+
+    #[test]
+    fn skip_unsupported_algorithms() {
+        let parameters = |name: &str, algorithm: i32| OtpParameters {
+            secret: b"12345678901234567890".to_vec(),
+            name: name.to_string(),
+            issuer: String::new(),
+            algorithm,
+            digits: MigrationDigits::Six as i32,
+            otp_type: MigrationOtpType::Totp as i32,
+            counter: 0,
+        };
+        let payload = MigrationPayload {
+            otp_parameters: vec![
+                parameters("First", MigrationAlgorithm::Sha1 as i32),
+                parameters("Legacy", MigrationAlgorithm::Md5 as i32),
+                parameters("Unspecified", MigrationAlgorithm::Unspecified as i32),
+                parameters("Unknown", 9),
+                parameters("Last", MigrationAlgorithm::Sha512 as i32),
+            ],
+            version: 1,
+            batch_size: 1,
+            batch_index: 0,
+            batch_id: 1,
+        };
+        let uris = decode_uri(&payload_uri(&payload)).unwrap();
+        let labels = uris
+            .iter()
+            .map(|uri| uri.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, vec!["First", "Last"]);
+        assert_eq!(uris[1].token.algorithm, Algorithm::Sha512);
+    }
+
+    #[test]
+    fn encode_batches() {
+        let uris = (0..25)
+            .map(|i| OtpAuthUri {
+                issuer: Some(format!("Issuer {}", i)),
+                label: format!("user{}@example.com", i),
+                token: Token {
+                    secret: vec![i as u8; 20],
+                    digits: 8,
+                    algorithm: Algorithm::Sha512,
+                    ..Token::default()
+                },
+                counter: 0,
+            })
+            .collect::<Vec<_>>();
+        let encoded = encode_uris(&uris, DEFAULT_BATCH_SIZE).unwrap();
+        assert_eq!(encoded.len(), 3);
+
+        let decoded = encoded
+            .iter()
+            .flat_map(|uri| decode_uri(uri).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, uris);
+
+        let data = encoded[2].split_once("data=").unwrap().1;
+        let data = urlencoding::decode(data).unwrap();
+        let payload =
+            MigrationPayload::decode(BASE64.decode(data.as_bytes()).unwrap().as_slice()).unwrap();
+        assert_eq!(payload.otp_parameters.len(), 5);
+        assert_eq!(payload.batch_size, 3);
+        assert_eq!(payload.batch_index, 2);
+    }
+
+    #[test]
+    fn encode_unsupported() {
+        let uri = |digits, step| OtpAuthUri {
+            issuer: None,
+            label: "VPN".to_string(),
+            token: Token {
+                secret: vec![1; 20],
+                digits,
+                step,
+                ..Token::default()
+            },
+            counter: 0,
+        };
+        assert!(encode_uris(&[uri(8, 30)], DEFAULT_BATCH_SIZE).is_ok());
+        assert!(encode_uris(&[uri(6, 30), uri(7, 30)], DEFAULT_BATCH_SIZE).is_err());
+        assert!(encode_uris(&[uri(6, 60)], DEFAULT_BATCH_SIZE).is_err());
+    }
+
+    #[test]
+    fn decode_invalid_uri() {
+        assert!(decode_uri("otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP").is_err());
+        assert!(decode_uri("otpauth-migration://offline?data=%%%").is_err());
+    }
+}
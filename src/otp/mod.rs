@@ -1,2 +1,5 @@
 pub mod generator;
+pub mod migration;
+pub mod otpauth;
+pub mod qr;
 pub mod token;
@@ -0,0 +1,231 @@
+use crate::db::models::record::Record;
+use crate::otp::migration;
+use crate::otp::token::{OtpType, Token};
+use crate::TotpError;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+const SCHEME: &str = "otpauth://";
+
+/// A Key URI as described by https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtpAuthUri {
+    pub issuer: Option<String>,
+    pub label: String,
+    pub token: Token,
+    pub counter: u64,
+}
+
+impl OtpAuthUri {
+    /// Parse either a single otpauth:// URI or an otpauth-migration:// batch
+    pub fn parse_all(value: &str) -> Result<Vec<Self>, TotpError> {
+        if value.trim().to_lowercase().starts_with(migration::SCHEME) {
+            migration::decode_uri(value)
+        } else {
+            Ok(vec![value.parse()?])
+        }
+    }
+
+    pub fn from_record(record: &Record) -> Result<Self, TotpError> {
+        let token = record.token.clone().ok_or_else(|| {
+            TotpError::TotpUrl(format!("id {} does not have a secret", record.id))
+        })?;
+        let account = record.account.clone().unwrap_or_default();
+        // The issuer is only meaningful when there is a separate user to label the account with
+        let (issuer, label) = match record.user.clone().filter(|u| !u.is_empty()) {
+            Some(user) => (Some(account), user),
+            None => (None, account),
+        };
+        Ok(Self {
+            issuer,
+            label,
+            token,
+            counter: record.counter,
+        })
+    }
+}
+
+impl From<OtpAuthUri> for Record {
+    fn from(uri: OtpAuthUri) -> Self {
+        let (account, user) = match uri.issuer {
+            Some(issuer) => (issuer, Some(uri.label).filter(|l| !l.is_empty())),
+            None => (uri.label, None),
+        };
+        Record {
+            account: Some(account),
+            user,
+            token: Some(uri.token),
+            counter: uri.counter,
+            ..Record::default()
+        }
+    }
+}
+
+impl Display for OtpAuthUri {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let secret = self.token.to_string().trim_end_matches('=').to_string();
+        write!(f, "{}{}/", SCHEME, self.token.otp_type)?;
+        if let Some(issuer) = self.issuer.as_ref() {
+            write!(f, "{}:", urlencoding::encode(issuer))?;
+        }
+        write!(f, "{}?secret={}", urlencoding::encode(&self.label), secret)?;
+        if let Some(issuer) = self.issuer.as_ref() {
+            write!(f, "&issuer={}", urlencoding::encode(issuer))?;
+        }
+        write!(
+            f,
+            "&algorithm={}&digits={}",
+            self.token.algorithm, self.token.digits
+        )?;
+        match self.token.otp_type {
+            OtpType::Totp => write!(f, "&period={}", self.token.step),
+            OtpType::Hotp => write!(f, "&counter={}", self.counter),
+        }
+    }
+}
+
+impl FromStr for OtpAuthUri {
+    type Err = TotpError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let rest = value
+            .get(..SCHEME.len())
+            .filter(|scheme| scheme.eq_ignore_ascii_case(SCHEME))
+            .map(|_| &value[SCHEME.len()..])
+            .ok_or_else(|| TotpError::TotpUrl(format!("Expected {} URI", SCHEME)))?;
+        let (otp_type, rest) = rest
+            .split_once('/')
+            .ok_or_else(|| TotpError::TotpUrl("Missing OTP type".to_string()))?;
+        let otp_type = match otp_type.to_lowercase().as_str() {
+            "totp" => OtpType::Totp,
+            "hotp" => OtpType::Hotp,
+            other => return Err(TotpError::TotpUrl(format!("Unknown OTP type {}", other))),
+        };
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        // Prefer a literal separator so an encoded colon can appear inside the issuer
+        let (mut issuer, label) = match path.split_once(':') {
+            Some((issuer, label)) => (Some(decode(issuer)?), decode(label)?),
+            None => match decode(path)?.split_once(':') {
+                Some((issuer, label)) => (Some(issuer.to_string()), label.to_string()),
+                None => (None, decode(path)?),
+            },
+        };
+        let label = label.trim_start().to_string();
+
+        let mut token = Token {
+            otp_type,
+            ..Token::default()
+        };
+        let mut secret = None;
+        let mut counter = None;
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = decode(&value.replace('+', " "))?;
+            match key.to_lowercase().as_str() {
+                "secret" => secret = Some(Token::from_str(&value)?.secret),
+                // The parameter is preferred over the label prefix when both are present
+                "issuer" => issuer = Some(value).filter(|v| !v.is_empty()),
+                "algorithm" => token.algorithm = value.parse()?,
+                "digits" => token.digits = parse_number(key, &value)?,
+                "period" => token.step = parse_number(key, &value)?,
+                "counter" => counter = Some(parse_number(key, &value)?),
+                _ => {}
+            }
+        }
+        token.secret = secret.ok_or_else(|| TotpError::TotpUrl("Missing secret".to_string()))?;
+        if otp_type == OtpType::Hotp && counter.is_none() {
+            return Err(TotpError::TotpUrl("Missing HOTP counter".to_string()));
+        }
+        token.validate()?;
+
+        Ok(Self {
+            issuer,
+            label,
+            token,
+            counter: counter.unwrap_or_default(),
+        })
+    }
+}
+
+fn decode(value: &str) -> Result<String, TotpError> {
+    urlencoding::decode(value)
+        .map(|v| v.into_owned())
+        .map_err(|e| TotpError::Utf8(e.to_string()))
+}
+
+fn parse_number<T: FromStr>(key: &str, value: &str) -> Result<T, TotpError> {
+    value
+        .parse()
+        .map_err(|_| TotpError::TotpUrl(format!("Invalid {} {}", key, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otp::token::Algorithm;
+
+    #[test]
+    fn parse_full_uri() {
+        let uri: OtpAuthUri = "otpauth://totp/ACME%20Co:john.doe@email.com?secret=HXDMVJECJJWSRB3HWIZR4IFUGFTMXBOZ&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60"
+            .parse()
+            .unwrap();
+        assert_eq!(uri.issuer, Some("ACME Co".to_string()));
+        assert_eq!(uri.label, "john.doe@email.com");
+        assert_eq!(uri.token.algorithm, Algorithm::Sha256);
+        assert_eq!(uri.token.digits, 8);
+        assert_eq!(uri.token.step, 60);
+
+        let record = Record::from(uri);
+        assert_eq!(record.account, Some("ACME Co".to_string()));
+        assert_eq!(record.user, Some("john.doe@email.com".to_string()));
+    }
+
+    #[test]
+    fn parse_minimal_uri() {
+        let uri: OtpAuthUri = "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP"
+            .parse()
+            .unwrap();
+        assert_eq!(uri.issuer, None);
+        assert_eq!(uri.label, "Example");
+        assert_eq!(uri.token, Token::from_str("JBSWY3DPEHPK3PXP").unwrap());
+        assert!("otpauth://totp/Example".parse::<OtpAuthUri>().is_err());
+        assert!("otpauth://hotp/Example?secret=JBSWY3DPEHPK3PXP"
+            .parse::<OtpAuthUri>()
+            .is_err());
+    }
+
+    #[test]
+    fn reject_invalid_parameters() {
+        for query in ["period=0", "digits=12", "digits=4"] {
+            let uri = format!("otpauth://totp/X?secret=JBSWY3DPEHPK3PXP&{}", query);
+            assert!(uri.parse::<OtpAuthUri>().is_err(), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let record = Record {
+            account: Some("Big Corp: EU".to_string()),
+            user: Some("alice&bob@example.com".to_string()),
+            token: Some(Token {
+                otp_type: OtpType::Hotp,
+                algorithm: Algorithm::Sha512,
+                ..Token::from_str("JBSWY3DPEHPK3PXP").unwrap()
+            }),
+            counter: 42,
+            ..Record::default()
+        };
+        let uri = OtpAuthUri::from_record(&record).unwrap().to_string();
+        assert_eq!(
+            uri,
+            "otpauth://hotp/Big%20Corp%3A%20EU:alice%26bob%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=Big%20Corp%3A%20EU&algorithm=SHA512&digits=6&counter=42"
+        );
+        let parsed = Record::from(uri.parse::<OtpAuthUri>().unwrap());
+        assert_eq!(parsed.account, record.account);
+        assert_eq!(parsed.user, record.user);
+        assert_eq!(parsed.token, record.token);
+        assert_eq!(parsed.counter, 42);
+    }
+}
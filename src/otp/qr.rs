@@ -0,0 +1,187 @@
+use crate::TotpError;
+use image::{ImageFormat, Luma};
+use qrcode::render::{svg, unicode};
+use qrcode::QrCode;
+use std::fs::OpenOptions;
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+/// Render `data` as a QR code built from Unicode half blocks
+///
+/// By default the code is drawn light on dark for dark terminal backgrounds, `invert` flips it.
+pub fn render_unicode(data: &str, invert: bool) -> Result<String, TotpError> {
+    let code = QrCode::new(data.as_bytes())?;
+    let (dark, light) = if invert {
+        (unicode::Dense1x2::Dark, unicode::Dense1x2::Light)
+    } else {
+        (unicode::Dense1x2::Light, unicode::Dense1x2::Dark)
+    };
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(dark)
+        .light_color(light)
+        .build())
+}
+
+/// Decode the contents of every QR code found in a PNG or JPEG image
+///
+/// Codes that cannot be read, like a cropped one next to a good one, are skipped with a warning.
+pub fn decode_file(path: &Path) -> Result<Vec<String>, TotpError> {
+    let image = image::open(path)
+        .map_err(|e| TotpError::Qr(format!("{}: {}", path.display(), e)))?
+        .to_luma8();
+    let mut prepared = rqrr::PreparedImage::prepare(image);
+    let grids = prepared.detect_grids();
+    let mut contents = vec![];
+    let mut last_error = None;
+    for grid in grids {
+        match grid.decode() {
+            Ok((_, content)) => contents.push(content),
+            Err(e) => {
+                log::warn!(
+                    "Skipping an unreadable QR code in {}: {}",
+                    path.display(),
+                    e
+                );
+                last_error = Some(e);
+            }
+        }
+    }
+    match last_error {
+        Some(e) if contents.is_empty() => Err(TotpError::Qr(format!("{}: {}", path.display(), e))),
+        _ => Ok(contents),
+    }
+}
+
+/// Write `data` as a QR code image, the format is taken from the `png` or `svg` extension
+///
+/// The code carries the secret, so on Unix the file is created readable by the owner only.
+pub fn write_file(data: &str, path: &Path) -> Result<(), TotpError> {
+    let code = QrCode::new(data.as_bytes())?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    let bytes = match extension.as_deref() {
+        Some("png") => {
+            let mut png = Cursor::new(Vec::new());
+            code.render::<Luma<u8>>()
+                .min_dimensions(256, 256)
+                .build()
+                .write_to(&mut png, ImageFormat::Png)
+                .map_err(|e| TotpError::Qr(e.to_string()))?;
+            png.into_inner()
+        }
+        Some("svg") => code
+            .render::<svg::Color>()
+            .min_dimensions(256, 256)
+            .build()
+            .into_bytes(),
+        _ => {
+            return Err(TotpError::Qr(format!(
+                "Unsupported QR code file type {}, use .png or .svg",
+                path.display()
+            )))
+        }
+    };
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(&bytes))
+        .map_err(|e| TotpError::Qr(format!("Cannot write {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_unicode_lines() {
+        let rendered =
+            render_unicode("otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP", false).unwrap();
+        let lines = rendered.lines().collect::<Vec<_>>();
+        assert!(!lines.is_empty());
+        let width = lines[0].chars().count();
+        assert!(lines.iter().all(|l| l.chars().count() == width));
+        // Two modules per line, so the code is roughly twice as wide as it is tall
+        assert!(width > lines.len());
+    }
+
+    #[test]
+    fn write_and_decode_png() {
+        let data = "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP";
+        let path = std::env::temp_dir().join(format!("trotp-qr-test-{}.png", std::process::id()));
+        write_file(data, &path).unwrap();
+        let decoded = decode_file(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(decoded.unwrap(), vec![data.to_string()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let data = "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP";
+        for extension in ["png", "svg"] {
+            let path = std::env::temp_dir().join(format!(
+                "trotp-qr-private-{}.{}",
+                std::process::id(),
+                extension
+            ));
+            let _ = std::fs::remove_file(&path);
+            write_file(data, &path).unwrap();
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            let _ = std::fs::remove_file(&path);
+            assert_eq!(mode & 0o777, 0o600, "{}", extension);
+        }
+    }
+
+    #[test]
+    fn decode_readable_codes() {
+        let render = |data: &str| {
+            QrCode::new(data.as_bytes())
+                .unwrap()
+                .render::<Luma<u8>>()
+                .module_dimensions(4, 4)
+                .build()
+        };
+        let good = render("otpauth://totp/Good?secret=JBSWY3DPEHPK3PXP");
+        let mut bad = render("otpauth://totp/Bad?secret=JBSWY3DPEHPK3PXP");
+        // Scribble over the middle of the second code, leaving its finder patterns to be detected
+        let (width, height) = bad.dimensions();
+        for y in height / 3..height * 2 / 3 {
+            for x in width / 3..width * 2 / 3 {
+                if (x / 4 + y / 4) % 3 == 0 {
+                    let pixel = bad.get_pixel_mut(x, y);
+                    pixel.0[0] = 255 - pixel.0[0];
+                }
+            }
+        }
+        let mut image = image::GrayImage::from_pixel(width * 2 + 40, height + 40, Luma([255]));
+        image::imageops::replace(&mut image, &good, 20, 20);
+        image::imageops::replace(&mut image, &bad, width as i64 + 20, 20);
+
+        let path =
+            std::env::temp_dir().join(format!("trotp-qr-partial-{}.png", std::process::id()));
+        image.save(&path).unwrap();
+        let both = decode_file(&path);
+        image::imageops::replace(&mut image, &bad, 20, 20);
+        image.save(&path).unwrap();
+        let only_bad = decode_file(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            both.unwrap(),
+            vec!["otpauth://totp/Good?secret=JBSWY3DPEHPK3PXP".to_string()]
+        );
+        assert!(matches!(only_bad, Err(TotpError::Qr(_))));
+    }
+
+    #[test]
+    fn write_file_rejects_unknown_extension() {
+        let path = std::env::temp_dir().join("trotp-qr-test.gif");
+        assert!(matches!(write_file("data", &path), Err(TotpError::Qr(_))));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::TotpError;
+use clap::ValueEnum;
 use data_encoding::BASE32;
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -6,12 +7,88 @@ use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 use totp_rs::Secret;
 
+/// The HMAC algorithm used to generate codes for a token
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, ValueEnum)]
+pub enum Algorithm {
+    /// HMAC-SHA1
+    #[default]
+    #[serde(rename = "SHA1")]
+    Sha1,
+    /// HMAC-SHA256
+    #[serde(rename = "SHA256")]
+    Sha256,
+    /// HMAC-SHA512
+    #[serde(rename = "SHA512")]
+    Sha512,
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = TotpError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_uppercase().replace('-', "").as_str() {
+            "SHA1" => Ok(Algorithm::Sha1),
+            "SHA256" => Ok(Algorithm::Sha256),
+            "SHA512" => Ok(Algorithm::Sha512),
+            _ => Err(TotpError::InvalidAlgorithm(value.to_string())),
+        }
+    }
+}
+
+impl From<Algorithm> for totp_rs::Algorithm {
+    fn from(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Sha1 => totp_rs::Algorithm::SHA1,
+            Algorithm::Sha256 => totp_rs::Algorithm::SHA256,
+            Algorithm::Sha512 => totp_rs::Algorithm::SHA512,
+        }
+    }
+}
+
+/// Whether codes are derived from the time (RFC 6238) or a moving counter (RFC 4226)
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, ValueEnum)]
+pub enum OtpType {
+    /// Time based
+    #[default]
+    #[serde(rename = "totp")]
+    Totp,
+    /// Counter based
+    #[serde(rename = "hotp")]
+    Hotp,
+}
+
+impl Display for OtpType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OtpType::Totp => "totp",
+            OtpType::Hotp => "hotp",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Token {
     pub secret: Vec<u8>,
     pub digits: usize,
     pub skew: u8,
     pub step: u64,
+    // Tokens stored before the algorithm was configurable are all SHA1
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    #[serde(default)]
+    pub otp_type: OtpType,
 }
 
 impl Default for Token {
@@ -21,10 +98,33 @@ impl Default for Token {
             digits: 6,
             skew: 1,
             step: 30,
+            algorithm: Algorithm::default(),
+            otp_type: OtpType::default(),
         }
     }
 }
 
+impl Token {
+    /// Reject parameters `totp_rs` cannot generate codes with, it only produces 6 to 8 digits
+    pub fn validate(&self) -> Result<(), TotpError> {
+        if self.secret.is_empty() {
+            return Err(TotpError::InvalidToken("The secret is empty".to_string()));
+        }
+        if !(6..=8).contains(&self.digits) {
+            return Err(TotpError::InvalidToken(format!(
+                "{} digits is not supported, use 6 to 8",
+                self.digits
+            )));
+        }
+        if self.step == 0 {
+            return Err(TotpError::InvalidToken(
+                "The step must be at least 1 second".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
 impl Display for Token {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let encoded = BASE32.encode(self.secret.as_slice());
@@ -71,4 +171,61 @@ mod tests {
         let token: Token = token_string.parse().unwrap();
         assert_eq!(token, Token::from_str("JBSWY3DPEHPK3PXP").unwrap());
     }
+
+    #[test]
+    fn missing_algorithm_defaults_to_sha1() {
+        let token: Token =
+            serde_json::from_str(r#"{"secret":[72,101],"digits":6,"skew":1,"step":30}"#).unwrap();
+        assert_eq!(token.algorithm, Algorithm::Sha1);
+        let token = Token {
+            algorithm: Algorithm::Sha512,
+            ..token
+        };
+        let json = serde_json::to_string(&token).unwrap();
+        assert!(json.contains(r#""algorithm":"SHA512""#));
+        assert_eq!(serde_json::from_str::<Token>(&json).unwrap(), token);
+    }
+
+    #[test]
+    fn algorithm_from_str() {
+        assert_eq!("sha-256".parse::<Algorithm>().unwrap(), Algorithm::Sha256);
+        assert_eq!("SHA512".parse::<Algorithm>().unwrap(), Algorithm::Sha512);
+        assert!("md5".parse::<Algorithm>().is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let token = Token::from_str("JBSWY3DPEHPK3PXP").unwrap();
+        assert!(token.validate().is_ok());
+        assert!(Token {
+            digits: 8,
+            ..token.clone()
+        }
+        .validate()
+        .is_ok());
+        assert!(Token {
+            digits: 9,
+            ..token.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(Token {
+            digits: 5,
+            ..token.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(Token {
+            step: 0,
+            ..token.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(Token {
+            secret: vec![],
+            ..token
+        }
+        .validate()
+        .is_err());
+    }
 }
@@ -0,0 +1,251 @@
+use crate::otp::otpauth::OtpAuthUri;
+use crate::otp::token::Token;
+use crate::ui::input::Input;
+use crate::{Record, TotpError};
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddField {
+    Account,
+    User,
+    Secret,
+    Password,
+    Note,
+    Digits,
+    Step,
+}
+
+impl AddField {
+    pub const ALL: [AddField; 7] = [
+        AddField::Account,
+        AddField::User,
+        AddField::Secret,
+        AddField::Password,
+        AddField::Note,
+        AddField::Digits,
+        AddField::Step,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AddField::Account => "Account",
+            AddField::User => "User",
+            AddField::Secret => "Secret",
+            AddField::Password => "Password",
+            AddField::Note => "Note",
+            AddField::Digits => "Digits",
+            AddField::Step => "Step",
+        }
+    }
+}
+
+/// The add account form, one input per `AddField`
+pub struct AddForm {
+    inputs: Vec<Input>,
+    pub selected: usize,
+    /// Parameters the form has no field for, taken from a pasted URI
+    template: Token,
+    counter: u64,
+    /// A problem with the whole form, such as a URI that could not be parsed
+    pub error: Option<String>,
+}
+
+impl Default for AddForm {
+    fn default() -> Self {
+        let token = Token::default();
+        let mut form = Self {
+            inputs: vec![Input::default(); AddField::ALL.len()],
+            selected: 0,
+            template: token.clone(),
+            counter: 0,
+            error: None,
+        };
+        form.set(AddField::Digits, token.digits.to_string());
+        form.set(AddField::Step, token.step.to_string());
+        form
+    }
+}
+
+impl AddForm {
+    /// The algorithm and type the account is added with
+    pub fn template(&self) -> &Token {
+        &self.template
+    }
+
+    pub fn input(&self, field: AddField) -> &Input {
+        &self.inputs[Self::index(field)]
+    }
+
+    pub fn input_mut(&mut self) -> &mut Input {
+        &mut self.inputs[self.selected]
+    }
+
+    pub fn is_last(&self) -> bool {
+        self.selected == AddField::ALL.len() - 1
+    }
+
+    pub fn next(&mut self) {
+        self.selected = (self.selected + 1) % AddField::ALL.len();
+    }
+
+    pub fn previous(&mut self) {
+        self.selected = (self.selected + AddField::ALL.len() - 1) % AddField::ALL.len();
+    }
+
+    /// Pasting an otpauth:// URI fills in every field, anything else goes into the selected one
+    pub fn paste(&mut self, text: &str) {
+        let text = text.trim();
+        if !text.to_lowercase().starts_with("otpauth://") {
+            self.input_mut().insert_str(&text.replace(['\r', '\n'], ""));
+            return;
+        }
+        match OtpAuthUri::from_str(text) {
+            Ok(uri) => {
+                let record = Record::from(uri);
+                let token = record.token.clone().unwrap_or_default();
+                self.set(AddField::Account, record.account.unwrap_or_default());
+                self.set(AddField::User, record.user.unwrap_or_default());
+                self.set(AddField::Secret, token.to_string());
+                self.set(AddField::Digits, token.digits.to_string());
+                self.set(AddField::Step, token.step.to_string());
+                self.template = token;
+                self.counter = record.counter;
+                self.error = None;
+            }
+            Err(e) => self.error = Some(format!("Invalid URI: {}", e)),
+        }
+    }
+
+    /// Why the value of `field` cannot be saved, checked as it is typed
+    pub fn field_error(&self, field: AddField) -> Option<String> {
+        let value = self.input(field).value().trim();
+        match field {
+            AddField::Account if value.is_empty() => Some("Required".to_string()),
+            AddField::Secret if value.is_empty() => Some("Required".to_string()),
+            AddField::Secret => Token::from_str(&value.replace(' ', ""))
+                .err()
+                .map(|_| "Not valid base32".to_string()),
+            AddField::Digits => match value.parse::<usize>() {
+                Ok(digits) if (6..=8).contains(&digits) => None,
+                _ => Some("Use 6 to 8".to_string()),
+            },
+            AddField::Step => match value.parse::<u64>() {
+                Ok(step) if step > 0 => None,
+                _ => Some("Seconds, at least 1".to_string()),
+            },
+            _ => None,
+        }
+    }
+
+    /// The record to add, or the first field that needs fixing
+    pub fn record(&self) -> Result<Record, TotpError> {
+        if let Some(field) = AddField::ALL
+            .into_iter()
+            .find(|field| self.field_error(*field).is_some())
+        {
+            return Err(TotpError::InvalidToken(format!(
+                "{}: {}",
+                field.label(),
+                self.field_error(field).unwrap_or_default()
+            )));
+        }
+        let value = |field| {
+            Some(self.input(field).value().trim().to_string()).filter(|value| !value.is_empty())
+        };
+        let secret = Token::from_str(&self.input(AddField::Secret).value().replace(' ', ""))?;
+        let token = Token {
+            secret: secret.secret,
+            digits: self
+                .input(AddField::Digits)
+                .value()
+                .trim()
+                .parse()
+                .unwrap_or_default(),
+            step: self
+                .input(AddField::Step)
+                .value()
+                .trim()
+                .parse()
+                .unwrap_or_default(),
+            ..self.template.clone()
+        };
+        token.validate()?;
+        Ok(Record {
+            account: value(AddField::Account),
+            user: value(AddField::User),
+            token: Some(token),
+            password: value(AddField::Password),
+            note: value(AddField::Note),
+            counter: self.counter,
+            ..Record::default()
+        })
+    }
+
+    fn set(&mut self, field: AddField, value: String) {
+        self.inputs[Self::index(field)] = Input::new(value);
+    }
+
+    fn index(field: AddField) -> usize {
+        AddField::ALL
+            .iter()
+            .position(|f| *f == field)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otp::token::{Algorithm, OtpType};
+
+    #[test]
+    fn validate_fields() {
+        let mut form = AddForm::default();
+        assert_eq!(
+            form.field_error(AddField::Account),
+            Some("Required".to_string())
+        );
+        assert!(form.record().is_err());
+        form.input_mut().insert_str("GitHub");
+        form.selected = 2;
+        form.input_mut().insert_str("JBSW Y3DP EHPK 3PX!");
+        assert_eq!(
+            form.field_error(AddField::Secret),
+            Some("Not valid base32".to_string())
+        );
+        form.input_mut().backspace();
+        form.input_mut().insert('P');
+        assert_eq!(form.field_error(AddField::Secret), None);
+        form.selected = 5;
+        form.input_mut().insert('9');
+        assert!(form.field_error(AddField::Digits).is_some());
+        form.input_mut().backspace();
+
+        let record = form.record().unwrap();
+        assert_eq!(record.account, Some("GitHub".to_string()));
+        assert_eq!(record.user, None);
+        assert_eq!(
+            record.token,
+            Some(Token::from_str("JBSWY3DPEHPK3PXP").unwrap())
+        );
+    }
+
+    #[test]
+    fn paste_uri() {
+        let mut form = AddForm::default();
+        form.paste("otpauth://hotp/ACME:alice?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA256&digits=8&counter=4\n");
+        assert_eq!(form.input(AddField::Account).value(), "ACME");
+        assert_eq!(form.input(AddField::User).value(), "alice");
+        assert_eq!(form.input(AddField::Digits).value(), "8");
+        let record = form.record().unwrap();
+        let token = record.token.unwrap();
+        assert_eq!(token.algorithm, Algorithm::Sha256);
+        assert_eq!(token.otp_type, OtpType::Hotp);
+        assert_eq!(record.counter, 4);
+
+        form.paste("otpauth://totp/broken");
+        assert!(form.error.is_some());
+        form.paste("plain\ntext");
+        assert_eq!(form.input(AddField::Account).value(), "ACMEplaintext");
+    }
+}
@@ -1,8 +1,14 @@
-use crate::ui::clip::set_clipboard;
-use crate::ui::state::{ActivePane, State};
+use crate::config::Config;
+use crate::otp::otpauth::OtpAuthUri;
+use crate::otp::qr;
+use crate::ui::add_form::AddForm;
+use crate::ui::clip::Clipboard;
+use crate::ui::input::Input;
+use crate::ui::keymap::Keymap;
+use crate::ui::state::{ActivePane, Copied, DetailInputType, InputMode, State};
 use crate::ui::widgets::popup::{Popup, Position, Size};
-use crate::{StorageTrait, TotpError};
-use chrono::Utc;
+use crate::{Record, StorageTrait, TotpError};
+use chrono::{NaiveDateTime, Utc};
 use std::ops::Add;
 use tui::style::{Color, Style};
 use tui::widgets::{ListState, TableState};
@@ -15,17 +21,49 @@ pub struct App {
     pub table_state: TableState,
     /// Detail View State
     pub detail_state: ListState,
+    /// Key bindings from the config file
+    pub keymap: Keymap,
+    /// The last key press or paste
+    last_activity: NaiveDateTime,
+    /// How long without activity before locking
+    lock_after: Option<chrono::Duration>,
+    /// How long copied values stay in the clipboard
+    clear_after: Option<chrono::Duration>,
+    clipboard: Clipboard,
 }
 impl App {
-    pub fn new<T: StorageTrait + 'static>(storage: T) -> Result<Self, TotpError> {
+    pub fn new<T: StorageTrait + 'static>(storage: T, config: &Config) -> Result<Self, TotpError> {
         Ok(Self {
             state: State::new(storage)?,
             table_state: TableState::default(),
             detail_state: ListState::default(),
+            keymap: Keymap::from_config(&config.keymap)?,
+            last_activity: Utc::now().naive_utc(),
+            lock_after: Some(config.tui.lock_after)
+                .filter(|seconds| *seconds > 0)
+                .map(|seconds| chrono::Duration::seconds(seconds as i64)),
+            clear_after: Some(config.clipboard.clear_after)
+                .filter(|seconds| *seconds > 0)
+                .map(|seconds| chrono::Duration::seconds(seconds as i64)),
+            clipboard: Clipboard::new(config.clipboard.backend),
         })
     }
 
     pub fn tick(&mut self) {
+        if let Some(copied) = self.state.copied.as_ref() {
+            if copied.clear_at <= Utc::now().naive_utc() {
+                self.clear_copied();
+            } else {
+                self.show_copied();
+            }
+        }
+        if let Some(lock_after) = self.lock_after {
+            if self.state.input_mode != InputMode::Locked
+                && Utc::now().naive_utc() - self.last_activity >= lock_after
+            {
+                self.lock();
+            }
+        }
         if let Some(popup) = self.state.show_popup.as_ref() {
             if let Some(show_until) = popup.show_until {
                 if show_until < Utc::now().naive_utc() {
@@ -35,6 +73,53 @@ impl App {
         }
     }
 
+    /// Postpone locking after a key press or paste
+    pub fn touch(&mut self) {
+        self.last_activity = Utc::now().naive_utc();
+    }
+
+    /// Hide every code and detail and drop the decrypted records until the password is entered
+    pub fn lock(&mut self) {
+        self.clear_copied();
+        self.state.records.clear();
+        self.state.items.clear();
+        self.state.display_otps.clear();
+        self.state.hotp_codes.clear();
+        self.state.add_form = None;
+        self.state.detail_input = Input::default();
+        self.state.unlock_input = Input::default();
+        self.state.unlock_error = None;
+        self.state.show_popup = None;
+        self.state.show_help = false;
+        if let Some(storage) = self.state.storage.as_mut() {
+            storage.lock();
+        }
+        self.state.input_mode = InputMode::Locked;
+    }
+
+    /// Check the password typed on the lock screen and reload the records
+    pub fn unlock(&mut self) -> Result<(), TotpError> {
+        let input = std::mem::take(&mut self.state.unlock_input);
+        let storage = self
+            .state
+            .storage
+            .as_mut()
+            .ok_or(TotpError::Storage("Storage not found".to_string()))?;
+        match storage.unlock(input.value()) {
+            Ok(_) => {
+                self.state.build_records()?;
+                self.state.unlock_error = None;
+                self.state.input_mode = InputMode::Normal;
+                self.touch();
+            }
+            Err(TotpError::Decryption(_)) => {
+                self.state.unlock_error = Some("Wrong password".to_string())
+            }
+            Err(e) => self.state.unlock_error = Some(e.to_string()),
+        }
+        Ok(())
+    }
+
     pub fn move_down(&mut self) {
         match self.state.active_pane {
             ActivePane::OtpTable => {
@@ -124,6 +209,298 @@ impl App {
         }
     }
 
+    /// The record selected in the table
+    pub fn selected_record(&self) -> Option<&Record> {
+        let (_, _, _, record_id) = self.state.display_otps.get(self.table_state.selected()?)?;
+        self.state.records.iter().find(|r| r.id == *record_id)
+    }
+
+    /// Edit the detail selected in the details pane
+    pub fn start_edit_detail(&mut self) {
+        let input_type =
+            DetailInputType::from_index(self.detail_state.selected().unwrap_or_default());
+        let Some(record) = self.selected_record() else {
+            return;
+        };
+        self.state.detail_input = Input::new(input_type.value(record).unwrap_or_default());
+        self.state.detail_input_type = input_type;
+        self.state.input_mode = InputMode::EditDetail;
+    }
+
+    /// Ask before saving the edited detail, leaving edit mode when nothing changed
+    pub fn confirm_edit_detail(&mut self) {
+        let Some(record) = self.selected_record() else {
+            self.state.input_mode = InputMode::Normal;
+            return;
+        };
+        let input_type = self.state.detail_input_type;
+        if input_type.value(record).unwrap_or_default() == self.state.detail_input.value() {
+            self.state.input_mode = InputMode::Normal;
+            return;
+        }
+        let message = format!(
+            "Save the new {} for {}?\nPress 'y' to save or 'n' to keep editing",
+            input_type.name(),
+            record.account.clone().unwrap_or_default()
+        );
+        self.state.input_mode = InputMode::EditConfirmation;
+        self.state.show_popup = Some(Popup::new(
+            "Confirm Edit".to_string(),
+            Some(message),
+            None,
+            Some(true),
+            Some(Style::default().fg(Color::Yellow)),
+            None,
+            None,
+        ));
+    }
+
+    /// Save the edited detail through the storage
+    pub fn save_detail(&mut self) -> Result<(), TotpError> {
+        self.state.input_mode = InputMode::Normal;
+        self.state.show_popup = None;
+        let Some(mut record) = self.selected_record().cloned() else {
+            return Ok(());
+        };
+        let input_type = self.state.detail_input_type;
+        input_type.set(&mut record, self.state.detail_input.value());
+        let storage = self
+            .state
+            .storage
+            .as_mut()
+            .ok_or(TotpError::Storage("Storage not found".to_string()))?;
+        match storage.edit_account(record) {
+            Ok(_) => {
+                self.state.build_records()?;
+                self.notify(
+                    "Saved",
+                    format!("Saved the {}", input_type.name()),
+                    Color::Green,
+                );
+            }
+            Err(e) => self.notify("Error Saving", e.to_string(), Color::Red),
+        }
+        Ok(())
+    }
+
+    pub fn start_add_account(&mut self) {
+        self.state.add_form = Some(AddForm::default());
+        self.state.input_mode = InputMode::AddAccount;
+    }
+
+    pub fn cancel_add_account(&mut self) {
+        self.state.add_form = None;
+        self.state.input_mode = InputMode::Normal;
+    }
+
+    /// Add the account in the form, keeping the form open with an error when it is not valid
+    pub fn save_add_account(&mut self) -> Result<(), TotpError> {
+        let Some(form) = self.state.add_form.as_mut() else {
+            return Ok(());
+        };
+        let record = match form.record() {
+            Ok(record) => record,
+            Err(TotpError::InvalidToken(message)) => {
+                form.error = Some(message);
+                return Ok(());
+            }
+            Err(e) => {
+                form.error = Some(e.to_string());
+                return Ok(());
+            }
+        };
+        let account = record.account.clone().unwrap_or_default();
+        let storage = self
+            .state
+            .storage
+            .as_mut()
+            .ok_or(TotpError::Storage("Storage not found".to_string()))?;
+        storage.add_account(record)?;
+        self.state.build_records()?;
+        self.cancel_add_account();
+        self.notify("Account Added", format!("Added {}", account), Color::Green);
+        Ok(())
+    }
+
+    /// Show a message at the top that closes by itself
+    fn notify(&mut self, title: &str, message: String, colour: Color) {
+        self.state.show_popup = Some(Popup::new(
+            title.to_string(),
+            Some(message),
+            Some(
+                Utc::now()
+                    .add(chrono::Duration::milliseconds(POPUP_DELAY))
+                    .naive_utc(),
+            ),
+            Some(true),
+            Some(Style::default().fg(colour)),
+            Some(Size { x: 30, y: 15 }),
+            Some(Position::Top),
+        ));
+    }
+
+    pub fn next_hotp(&mut self) -> Result<(), TotpError> {
+        let Some(record_id) = self
+            .table_state
+            .selected()
+            .and_then(|i| self.state.display_otps.get(i))
+            .map(|(_, _, _, record_id)| *record_id)
+        else {
+            return Ok(());
+        };
+        let generator = self
+            .state
+            .items
+            .iter()
+            .find(|(_, _, id)| *id == record_id)
+            .and_then(|(_, generator, _)| generator.clone())
+            .filter(|generator| generator.is_hotp());
+        if let (Some(generator), Some(storage)) = (generator, self.state.storage.as_mut()) {
+            let counter = storage.increment_counter(record_id)?;
+            self.state
+                .hotp_codes
+                .insert(record_id, generator.generate_hotp(counter));
+        }
+        Ok(())
+    }
+
+    pub fn show_qr_code(&mut self) {
+        let Some(record) = self
+            .table_state
+            .selected()
+            .and_then(|i| self.state.display_otps.get(i))
+            .and_then(|(_, _, _, id)| self.state.records.iter().find(|r| r.id == *id))
+        else {
+            return;
+        };
+        let (title, message, style) = match OtpAuthUri::from_record(record)
+            .and_then(|uri| qr::render_unicode(&uri.to_string(), false))
+        {
+            Ok(code) => (
+                record.account.clone().unwrap_or_default(),
+                format!("{}\nPress any key to close", code),
+                Style::default(),
+            ),
+            Err(e) => (
+                "Error Creating QR Code".to_string(),
+                e.to_string(),
+                Style::default().fg(Color::Red),
+            ),
+        };
+        self.state.input_mode = InputMode::QrCode;
+        self.state.show_popup = Some(Popup::new(
+            title,
+            Some(message),
+            None,
+            Some(false),
+            Some(style),
+            Some(Size { x: 90, y: 90 }),
+            None,
+        ));
+    }
+
+    pub fn toggle_next_code(&mut self) {
+        self.state.show_next_code = !self.state.show_next_code;
+    }
+
+    /// Copy the code that follows the selected account's current one
+    pub fn copy_next_code(&mut self) {
+        let Some(record_id) = self
+            .table_state
+            .selected()
+            .and_then(|i| self.state.display_otps.get(i))
+            .map(|(_, _, _, record_id)| *record_id)
+        else {
+            return;
+        };
+        let next = self
+            .state
+            .items
+            .iter()
+            .find(|(_, _, id)| *id == record_id)
+            .and_then(|(_, generator, _)| generator.as_ref())
+            .filter(|generator| !generator.is_hotp())
+            .and_then(|generator| generator.generate_next(None).ok());
+        let Some((code, _)) = next else {
+            return;
+        };
+        self.copy(code, "Next OTP Copied", "Successfully copied the next OTP");
+    }
+
+    /// Copy `value`, counting down in the popup until it is cleared from the clipboard again
+    fn copy(&mut self, value: String, title: &str, message: &str) {
+        if let Err(e) = self.clipboard.set(&value) {
+            self.notify("Error Copying", e.to_string(), Color::Red);
+            return;
+        }
+        let Some(clear_after) = self.clear_after else {
+            self.notify(title, message.to_string(), Color::Green);
+            return;
+        };
+        self.state.copied = Some(Copied {
+            value,
+            clear_at: Utc::now().naive_utc() + clear_after,
+            title: title.to_string(),
+            message: message.to_string(),
+        });
+        self.state.show_popup = None;
+        self.show_copied();
+    }
+
+    /// Update the countdown, unless another popup has replaced it
+    fn show_copied(&mut self) {
+        let Some(copied) = self.state.copied.as_ref() else {
+            return;
+        };
+        if self
+            .state
+            .show_popup
+            .as_ref()
+            .is_some_and(|popup| popup.title != copied.title)
+        {
+            return;
+        }
+        let remaining = (copied.clear_at - Utc::now().naive_utc()).num_milliseconds();
+        self.state.show_popup = Some(Popup::new(
+            copied.title.clone(),
+            Some(format!(
+                "{}\nClearing the clipboard in {}s",
+                copied.message,
+                (remaining + 999) / 1000
+            )),
+            Some(copied.clear_at),
+            Some(true),
+            Some(Style::default().fg(Color::Green)),
+            Some(Size { x: 30, y: 15 }),
+            Some(Position::Top),
+        ));
+    }
+
+    /// Empty the clipboard now if it still holds the copied value
+    pub fn clear_copied(&mut self) {
+        let Some(copied) = self.state.copied.take() else {
+            return;
+        };
+        let countdown = self
+            .state
+            .show_popup
+            .as_ref()
+            .is_none_or(|popup| popup.title == copied.title);
+        match self.clipboard.clear(&copied.value) {
+            Ok(true) if countdown => self.notify(
+                "Clipboard Cleared",
+                "Cleared the copied value".to_string(),
+                Color::Green,
+            ),
+            Ok(_) => {
+                if countdown {
+                    self.state.show_popup = None;
+                }
+            }
+            Err(e) => self.notify("Error Clearing Clipboard", e.to_string(), Color::Red),
+        }
+    }
+
     pub fn set_clipboard(&mut self) {
         #[cfg(feature = "cli-clipboard")]
         if self.state.show_popup.is_some() {
@@ -134,76 +511,87 @@ impl App {
             return;
         }
 
-        if let Some((title, message, colour)) = match self.state.active_pane {
-            ActivePane::OtpTable => {
-                if let Some(i) = self.table_state.selected() {
-                    match set_clipboard(self.state.display_otps[i].1.clone()) {
-                        Ok(_) => Some((
-                            "OTP Copied".to_string(),
-                            "Successfully copied OTP".to_string(),
-                            Color::Green,
-                        )),
-                        Err(e) => {
-                            Some(("Error Copying OTP".to_string(), e.to_string(), Color::Red))
-                        }
-                    }
-                } else {
-                    None
-                }
-            }
+        let Some(i) = self.table_state.selected() else {
+            return;
+        };
+        let (value, title, message) = match self.state.active_pane {
+            ActivePane::OtpTable => (
+                self.state.display_otps[i].1.clone(),
+                "OTP Copied",
+                "Successfully copied OTP",
+            ),
             ActivePane::DetailView => {
-                if let Some(i) = self.table_state.selected() {
-                    let record_id = self.state.display_otps[i].3;
-                    if let Some(record) = self.state.records.iter().find(|r| r.id == record_id) {
-                        let detail_selected_index =
-                            self.detail_state.selected().unwrap_or_default();
-                        let (value, content) = match detail_selected_index {
-                            1 => (
-                                record.user.clone().unwrap_or_default(),
-                                "Successfully copied username",
-                            ),
-                            2 => (
-                                record.note.clone().unwrap_or_default(),
-                                "Successfully copied note",
-                            ),
-                            _ => (
-                                record.password.clone().unwrap_or_default(),
-                                "Successfully copied password",
-                            ),
-                        };
-                        match set_clipboard(value) {
-                            Ok(_) => Some((
-                                "Detail Copied".to_string(),
-                                content.to_string(),
-                                Color::Green,
-                            )),
-                            Err(e) => Some((
-                                "Error Copying Details".to_string(),
-                                e.to_string(),
-                                Color::Red,
-                            )),
-                        }
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
+                let record_id = self.state.display_otps[i].3;
+                let Some(record) = self.state.records.iter().find(|r| r.id == record_id) else {
+                    return;
+                };
+                let (value, message) = match self.detail_state.selected().unwrap_or_default() {
+                    1 => (record.user.clone(), "Successfully copied username"),
+                    2 => (record.note.clone(), "Successfully copied note"),
+                    _ => (record.password.clone(), "Successfully copied password"),
+                };
+                (value.unwrap_or_default(), "Detail Copied", message)
             }
-        } {
-            self.state.show_popup = Some(Popup::new(
-                title,
-                Some(message),
-                Some(
-                    Utc::now()
-                        .add(chrono::Duration::milliseconds(POPUP_DELAY))
-                        .naive_utc(),
-                ),
-                Some(true),
-                Some(Style::default().fg(colour)),
-                Some(Size { x: 30, y: 15 }),
-                Some(Position::Top),
-            ));
+        };
+        self.copy(value, title, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::clip::{ClipboardBackend, CommandBackend};
+    use crate::ui::keymap::KeymapConfig;
+
+    fn app(clipboard: Clipboard) -> App {
+        App {
+            state: State::default(),
+            table_state: TableState::default(),
+            detail_state: ListState::default(),
+            keymap: Keymap::from_config(&KeymapConfig::default()).unwrap(),
+            last_activity: Utc::now().naive_utc(),
+            lock_after: None,
+            clear_after: Some(chrono::Duration::seconds(30)),
+            clipboard,
         }
     }
+
+    #[test]
+    fn clear_copied_after_delay() {
+        let file = std::env::temp_dir().join(format!("trotp-app-clip-{}", std::process::id()));
+        let file = file.to_str().unwrap();
+        let tool = || CommandBackend::new(&["tee", file], Some(&["cat", file]));
+        let mut app = app(Clipboard::with_backend(Box::new(tool())));
+        let expire = |app: &mut App| {
+            app.state.copied.as_mut().unwrap().clear_at =
+                Utc::now().naive_utc() - chrono::Duration::seconds(1);
+        };
+
+        app.copy("123456".to_string(), "Copied", "Copied the code");
+        assert_eq!(tool().get().unwrap(), Some("123456".to_string()));
+        // Nothing happens before the delay
+        app.tick();
+        assert!(app.state.copied.is_some());
+        assert_eq!(tool().get().unwrap(), Some("123456".to_string()));
+        expire(&mut app);
+        app.tick();
+        assert!(app.state.copied.is_none());
+        assert_eq!(tool().get().unwrap(), Some(String::new()));
+        assert_eq!(
+            app.state
+                .show_popup
+                .as_ref()
+                .map(|popup| popup.title.as_str()),
+            Some("Clipboard Cleared")
+        );
+
+        // Something copied since is left alone
+        app.copy("654321".to_string(), "Copied", "Copied the code");
+        tool().set("something else").unwrap();
+        expire(&mut app);
+        app.tick();
+        assert!(app.state.copied.is_none());
+        assert_eq!(tool().get().unwrap(), Some("something else".to_string()));
+        std::fs::remove_file(file).unwrap();
+    }
 }
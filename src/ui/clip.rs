@@ -1,22 +1,361 @@
 use crate::errors::TotpError;
-use std::process::Command;
+use data_encoding::BASE64;
+use serde::Deserialize;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
 
-pub fn set_clipboard(content: String) -> Result<(), TotpError> {
+/// Somewhere copied values can be put, and read back to check before clearing them
+pub trait ClipboardBackend {
+    fn name(&self) -> &str;
+    fn set(&mut self, content: &str) -> Result<(), TotpError>;
+    /// What the clipboard holds, `None` when the backend cannot read it
+    fn get(&mut self) -> Result<Option<String>, TotpError>;
+}
+
+/// The `backend` setting of the `[clipboard]` config section
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    #[default]
+    Auto,
+    Arboard,
+    WlCopy,
+    Xclip,
+    Xsel,
+    Pbcopy,
+    #[serde(rename = "clip.exe")]
+    ClipExe,
+    Osc52,
+}
+
+impl Backend {
+    fn create(&self) -> Result<Box<dyn ClipboardBackend>, TotpError> {
+        Ok(match self {
+            Backend::Auto => return detect(),
+            #[cfg(feature = "arboard")]
+            Backend::Arboard => Box::new(Arboard::new()?),
+            #[cfg(not(feature = "arboard"))]
+            Backend::Arboard => {
+                return Err(TotpError::ClipboardError(
+                    "Built without arboard".to_string(),
+                ))
+            }
+            Backend::WlCopy => Box::new(CommandBackend::new(
+                &["wl-copy"],
+                Some(&["wl-paste", "--no-newline"]),
+            )),
+            Backend::Xclip => Box::new(CommandBackend::new(
+                &["xclip", "-selection", "clipboard", "-in"],
+                Some(&["xclip", "-selection", "clipboard", "-out"]),
+            )),
+            Backend::Xsel => Box::new(CommandBackend::new(
+                &["xsel", "--clipboard", "--input"],
+                Some(&["xsel", "--clipboard", "--output"]),
+            )),
+            Backend::Pbcopy => Box::new(CommandBackend::new(&["pbcopy"], Some(&["pbpaste"]))),
+            Backend::ClipExe => Box::new(CommandBackend {
+                trim_newline: true,
+                ..CommandBackend::new(
+                    &["clip.exe"],
+                    Some(&["powershell.exe", "-NoProfile", "-Command", "Get-Clipboard"]),
+                )
+            }),
+            Backend::Osc52 => Box::new(Osc52),
+        })
+    }
+}
+
+/// Pick the first backend that can work here
+///
+/// Tools for the desktop session come first, with arboard ahead of them unless built with the
+/// `clip` feature, and OSC 52 through the terminal is the last resort, e.g. over SSH.
+fn detect() -> Result<Box<dyn ClipboardBackend>, TotpError> {
+    let has_env = |name: &str| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+    let mut candidates = vec![];
+    if !cfg!(feature = "clip") {
+        candidates.push(Backend::Arboard);
+    }
+    if cfg!(target_os = "windows") {
+        candidates.push(Backend::ClipExe);
+    } else if cfg!(target_os = "macos") {
+        candidates.push(Backend::Pbcopy);
+    } else {
+        if has_env("WAYLAND_DISPLAY") && in_path("wl-copy") {
+            candidates.push(Backend::WlCopy);
+        }
+        if has_env("DISPLAY") {
+            if in_path("xclip") {
+                candidates.push(Backend::Xclip);
+            }
+            if in_path("xsel") {
+                candidates.push(Backend::Xsel);
+            }
+        }
+    }
     if cfg!(feature = "clip") {
-        let executable = if cfg!(target_os = "windows") {
-            "clip.exe"
+        candidates.push(Backend::Arboard);
+    }
+    candidates.push(Backend::Osc52);
+    let mut errors = vec![];
+    for candidate in candidates {
+        match candidate.create() {
+            Ok(backend) => return Ok(backend),
+            Err(e) => errors.push(format!("{:?}", e)),
+        }
+    }
+    Err(TotpError::ClipboardError(format!(
+        "No clipboard found: {}",
+        errors.join(", ")
+    )))
+}
+
+fn in_path(program: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|paths| {
+        std::env::split_paths(&paths).any(|dir| Path::new(&dir).join(program).is_file())
+    })
+}
+
+/// The clipboard in use, found on first use so the TUI starts without one
+pub struct Clipboard {
+    choice: Backend,
+    backend: Option<Box<dyn ClipboardBackend>>,
+}
+
+impl Clipboard {
+    pub fn new(choice: Backend) -> Self {
+        Self {
+            choice,
+            backend: None,
+        }
+    }
+
+    /// A clipboard using `backend` instead of finding one
+    #[cfg(test)]
+    pub fn with_backend(backend: Box<dyn ClipboardBackend>) -> Self {
+        Self {
+            choice: Backend::Auto,
+            backend: Some(backend),
+        }
+    }
+
+    fn backend(&mut self) -> Result<&mut Box<dyn ClipboardBackend>, TotpError> {
+        if self.backend.is_none() {
+            let backend = self.choice.create()?;
+            log::debug!("Using the {} clipboard", backend.name());
+            self.backend = Some(backend);
+        }
+        self.backend
+            .as_mut()
+            .ok_or_else(|| TotpError::ClipboardError("No clipboard".to_string()))
+    }
+
+    pub fn set(&mut self, content: &str) -> Result<(), TotpError> {
+        self.backend()?.set(content)
+    }
+
+    /// Empty the clipboard if it still holds `content`, returning whether it did
+    ///
+    /// A clipboard that cannot be read, like OSC 52, is always emptied.
+    pub fn clear(&mut self, content: &str) -> Result<bool, TotpError> {
+        let backend = self.backend()?;
+        if backend.get()?.is_some_and(|current| current != content) {
+            return Ok(false);
+        }
+        backend.set("")?;
+        Ok(true)
+    }
+}
+
+/// The system clipboard through arboard, kept open so X11 keeps serving what was copied
+#[cfg(feature = "arboard")]
+pub struct Arboard(arboard::Clipboard);
+
+#[cfg(feature = "arboard")]
+impl Arboard {
+    pub fn new() -> Result<Self, TotpError> {
+        arboard::Clipboard::new()
+            .map(Arboard)
+            .map_err(|e| TotpError::ClipboardError(e.to_string()))
+    }
+}
+
+#[cfg(feature = "arboard")]
+impl ClipboardBackend for Arboard {
+    fn name(&self) -> &str {
+        "arboard"
+    }
+
+    fn set(&mut self, content: &str) -> Result<(), TotpError> {
+        let result = if content.is_empty() {
+            self.0.clear()
         } else {
-            "xsel --clipboard --input"
+            self.0.set_text(content)
+        };
+        result.map_err(|e| TotpError::ClipboardError(e.to_string()))
+    }
+
+    fn get(&mut self) -> Result<Option<String>, TotpError> {
+        match self.0.get_text() {
+            Ok(text) => Ok(Some(text)),
+            Err(arboard::Error::ContentNotAvailable) => Ok(Some(String::new())),
+            Err(e) => Err(TotpError::ClipboardError(e.to_string())),
+        }
+    }
+}
+
+/// A command line tool that copies its stdin, run directly without a shell
+pub struct CommandBackend {
+    copy: Vec<String>,
+    paste: Option<Vec<String>>,
+    /// The paste command ends its output with a line break
+    trim_newline: bool,
+}
+
+impl CommandBackend {
+    pub fn new(copy: &[&str], paste: Option<&[&str]>) -> Self {
+        let owned = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        Self {
+            copy: owned(copy),
+            paste: paste.map(owned),
+            trim_newline: false,
+        }
+    }
+
+    fn command(args: &[String]) -> Command {
+        let mut command = Command::new(&args[0]);
+        command.args(&args[1..]);
+        command
+    }
+}
+
+impl ClipboardBackend for CommandBackend {
+    fn name(&self) -> &str {
+        &self.copy[0]
+    }
+
+    fn set(&mut self, content: &str) -> Result<(), TotpError> {
+        let error =
+            |e: std::io::Error| TotpError::ClipboardError(format!("{}: {}", self.copy[0], e));
+        // xclip and wl-copy stay behind to serve the selection, waiting on their output would hang
+        let mut child = Self::command(&self.copy)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(error)?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(content.as_bytes()).map_err(error)?;
+        }
+        let status = child.wait().map_err(error)?;
+        if !status.success() {
+            return Err(TotpError::ClipboardError(format!(
+                "{} failed with {}",
+                self.copy[0], status
+            )));
+        }
+        Ok(())
+    }
+
+    fn get(&mut self) -> Result<Option<String>, TotpError> {
+        let Some(paste) = self.paste.as_ref() else {
+            return Ok(None);
         };
-        Command::new("sh")
-            .args(["-c", &format!("echo -n '{}' | {}", content, executable)])
+        let output = Self::command(paste)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
             .output()
-            .map_err(|e| TotpError::ClipboardError(e.to_string()))?;
-    } else if cfg!(feature = "arboard") {
-        let mut clipboard = arboard::Clipboard::new().unwrap();
-        clipboard
-            .set_text(content)
-            .map_err(|e| TotpError::ClipboardError(e.to_string()))?;
-    }
-    Ok(())
+            .map_err(|e| TotpError::ClipboardError(format!("{}: {}", paste[0], e)))?;
+        if !output.status.success() {
+            return Err(TotpError::ClipboardError(format!(
+                "{} failed with {}",
+                paste[0], output.status
+            )));
+        }
+        let content = String::from_utf8_lossy(&output.stdout);
+        Ok(Some(if self.trim_newline {
+            content.trim_end_matches(['\r', '\n']).to_string()
+        } else {
+            content.to_string()
+        }))
+    }
+}
+
+/// Asks the terminal to set the clipboard with an OSC 52 escape sequence, which works over SSH
+pub struct Osc52;
+
+impl Osc52 {
+    /// The escape sequence, wrapped for tmux to pass it on to the outer terminal as well
+    fn sequence(content: &str, tmux: bool) -> String {
+        let sequence = format!("\x1b]52;c;{}\x07", BASE64.encode(content.as_bytes()));
+        if tmux {
+            format!("{}\x1bPtmux;\x1b{}\x1b\\", sequence, sequence)
+        } else {
+            sequence
+        }
+    }
+}
+
+impl ClipboardBackend for Osc52 {
+    fn name(&self) -> &str {
+        "OSC 52"
+    }
+
+    fn set(&mut self, content: &str) -> Result<(), TotpError> {
+        let sequence = Self::sequence(content, std::env::var_os("TMUX").is_some());
+        let error = |e: std::io::Error| TotpError::ClipboardError(e.to_string());
+        if std::io::stdout().is_terminal() {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(sequence.as_bytes()).map_err(error)?;
+            stdout.flush().map_err(error)
+        } else if std::io::stderr().is_terminal() {
+            std::io::stderr()
+                .write_all(sequence.as_bytes())
+                .map_err(error)
+        } else {
+            Err(TotpError::ClipboardError(
+                "OSC 52 needs a terminal".to_string(),
+            ))
+        }
+    }
+
+    fn get(&mut self) -> Result<Option<String>, TotpError> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_backend_without_shell() {
+        let file = std::env::temp_dir().join(format!("trotp-clip-{}", std::process::id()));
+        let file = file.to_str().unwrap();
+        let tool = || CommandBackend::new(&["tee", file], Some(&["cat", file]));
+        let mut clipboard = Clipboard::with_backend(Box::new(tool()));
+        // Quotes and shell syntax are copied as they are
+        let content = "it's $(touch /tmp/pwned) `id`; echo";
+        clipboard.set(content).unwrap();
+        assert_eq!(tool().get().unwrap(), Some(content.to_string()));
+
+        assert!(!clipboard.clear("something else").unwrap());
+        assert_eq!(tool().get().unwrap(), Some(content.to_string()));
+        assert!(clipboard.clear(content).unwrap());
+        assert_eq!(tool().get().unwrap(), Some(String::new()));
+        std::fs::remove_file(file).unwrap();
+
+        let mut missing = CommandBackend::new(&["trotp-no-such-clipboard"], None);
+        assert!(missing.set("code").is_err());
+        assert_eq!(missing.get().unwrap(), None);
+    }
+
+    #[test]
+    fn osc52_sequence() {
+        assert_eq!(Osc52::sequence("123456", false), "\x1b]52;c;MTIzNDU2\x07");
+        assert_eq!(
+            Osc52::sequence("", true),
+            "\x1b]52;c;\x07\x1bPtmux;\x1b\x1b]52;c;\x07\x1b\\"
+        );
+    }
 }
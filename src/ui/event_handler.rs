@@ -6,10 +6,12 @@ use std::sync::mpsc;
 use std::thread;
 use std::time::{Duration, Instant};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum Event {
     /// Key press.
     Key(KeyEvent),
+    /// Bracketed paste.
+    Paste(String),
     /// Mouse click/scroll.
     #[allow(dead_code)]
     Mouse(MouseEvent),
@@ -54,9 +56,8 @@ impl EventHandler {
                             CrosstermEvent::Key(e) => sender.send(Event::Key(e)),
                             CrosstermEvent::Mouse(e) => sender.send(Event::Mouse(e)),
                             CrosstermEvent::Resize(w, h) => sender.send(Event::Resize(w, h)),
-                            CrosstermEvent::FocusGained
-                            | CrosstermEvent::FocusLost
-                            | CrosstermEvent::Paste(_) => Ok(()),
+                            CrosstermEvent::Paste(text) => sender.send(Event::Paste(text)),
+                            CrosstermEvent::FocusGained | CrosstermEvent::FocusLost => Ok(()),
                         }
                         .map_err(|_| UiEvent("Failed to send UI event".to_string()))
                         .expect("Failed to send UI event")
@@ -81,6 +82,17 @@ impl EventHandler {
     pub fn next(&self) -> Result<Event, TotpError> {
         self.receiver.recv().map_err(|e| e.into())
     }
+
+    /// An event handler that never reads the terminal, for driving the key handlers in tests
+    #[cfg(test)]
+    pub fn detached() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            handler: thread::spawn(|| {}),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -98,6 +110,7 @@ mod tests {
                 });
             }
             match events.next()? {
+                #[allow(clippy::collapsible_match)]
                 Event::Key(key_event) => {
                     if key_event.code == KeyCode::Esc {
                         assert_eq!(1, step);
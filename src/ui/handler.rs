@@ -1,4 +1,6 @@
-use crate::ui::state::{ActivePane, InputMode};
+use crate::ui::input::Input;
+use crate::ui::keymap::Action;
+use crate::ui::state::{ActivePane, DetailInputType, InputMode};
 use crate::{App, TotpError, Tui};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use tui::backend::Backend;
@@ -15,37 +17,80 @@ pub fn handle_key_events<B: Backend>(
     let code = key_event.code;
     let modifiers = key_event.modifiers;
 
-    match (code, modifiers) {
-        (KeyCode::Char('c'), KeyModifiers::CONTROL) => app.state.running = false,
-        (KeyCode::Down, _) => app.move_down(),
-        (KeyCode::Up, _) => app.move_up(),
-        (KeyCode::Enter, _) => app.set_clipboard(),
-        (KeyCode::Tab, _) => app.toggle_list_detail_mode(),
-        (KeyCode::End, _) => app.move_to_end(),
-        (KeyCode::Home, _) => app.move_to_start(),
+    if (code, modifiers) == (KeyCode::Char('c'), KeyModifiers::CONTROL) {
+        app.state.running = false;
+        return Ok(());
+    }
+    app.touch();
+    // Any key closes the help
+    if app.state.show_help {
+        app.state.show_help = false;
+        return Ok(());
+    }
+    let editing = matches!(
+        app.state.input_mode,
+        InputMode::EditDetail
+            | InputMode::EditConfirmation
+            | InputMode::AddAccount
+            | InputMode::Locked
+    );
+    // Popups waiting for an answer get every key but help, so 'y' confirms a delete even when it is bound
+    let modal = matches!(
+        app.state.input_mode,
+        InputMode::DeleteConfirmation | InputMode::QrCode
+    );
+    // Characters typed into an input are never bindings
+    let typing = (editing || app.state.input_mode == InputMode::FilterList)
+        && matches!(code, KeyCode::Char(_))
+        && !modifiers.contains(KeyModifiers::CONTROL);
+    let action = app
+        .keymap
+        .action(&key_event)
+        .filter(|action| !typing && (!modal || *action == Action::Help));
+    match action {
+        Some(Action::Help) => {
+            app.state.show_help = true;
+            return Ok(());
+        }
+        // The editor uses these keys itself
+        _ if editing => {}
+        Some(Action::Down) => app.move_down(),
+        Some(Action::Up) => app.move_up(),
+        Some(Action::Copy) => app.set_clipboard(),
+        Some(Action::TogglePane) => app.toggle_list_detail_mode(),
+        Some(Action::Bottom) => app.move_to_end(),
+        Some(Action::Top) => app.move_to_start(),
         _ => {}
     };
     match app.state.input_mode {
-        InputMode::Normal => handle_normal_mode(key_event, app),
+        InputMode::Normal => {
+            if let Some(action) = action {
+                handle_normal_mode(action, app)?
+            }
+        }
         InputMode::FilterList => handle_input_mode(key_event, app),
         InputMode::EditDetail => handle_edit_details(key_event, app),
+        InputMode::EditConfirmation => handle_edit_confirmation(key_event, app)?,
         InputMode::DeleteConfirmation => handle_delete_confirmation(key_event, app)?,
+        InputMode::QrCode => handle_qr_code(app),
+        InputMode::AddAccount => handle_add_account(key_event, app)?,
+        InputMode::Locked => handle_locked(key_event, app)?,
     }
 
     Ok(())
 }
 
-pub fn handle_normal_mode(key_event: KeyEvent, app: &mut App) {
-    let code = key_event.code;
-    let modifiers = key_event.modifiers;
-    match (code, modifiers) {
-        (KeyCode::Char('/'), _) => app.state.input_mode = InputMode::FilterList,
-        (KeyCode::Char('e'), _) => {
+pub fn handle_normal_mode(action: Action, app: &mut App) -> Result<(), TotpError> {
+    match action {
+        Action::Filter => app.state.input_mode = InputMode::FilterList,
+        #[allow(clippy::collapsible_match)]
+        Action::Edit => {
             if app.state.active_pane == ActivePane::DetailView {
-                app.state.input_mode = InputMode::EditDetail;
+                app.start_edit_detail();
             }
         }
-        (KeyCode::Char('d'), _) => {
+        #[allow(clippy::collapsible_match)]
+        Action::Delete => {
             if app.state.active_pane == ActivePane::OtpTable && app.table_state.selected().is_some()
             {
                 app.state.input_mode = InputMode::DeleteConfirmation;
@@ -60,17 +105,94 @@ pub fn handle_normal_mode(key_event: KeyEvent, app: &mut App) {
                 });
             }
         }
-        (KeyCode::Char('q'), _) => app.state.running = false,
+        Action::NextHotp if app.state.active_pane == ActivePane::OtpTable => app.next_hotp()?,
+        Action::Qr if app.state.active_pane == ActivePane::OtpTable => app.show_qr_code(),
+        Action::Add => app.start_add_account(),
+        Action::ToggleNext => app.toggle_next_code(),
+        Action::CopyNext if app.state.active_pane == ActivePane::OtpTable => app.copy_next_code(),
+        Action::Quit => app.state.running = false,
         _ => {}
     }
+    Ok(())
 }
 
 pub fn handle_edit_details(key_event: KeyEvent, app: &mut App) {
-    let code = key_event.code;
-    let modifiers = key_event.modifiers;
-    match (code, modifiers) {
-        (KeyCode::Esc, _) => app.state.input_mode = InputMode::Normal,
-        (KeyCode::Char('e'), _) => app.state.running = false,
+    match key_event.code {
+        KeyCode::Esc => app.state.input_mode = InputMode::Normal,
+        KeyCode::Enter => app.confirm_edit_detail(),
+        _ => {
+            app.state.detail_input.handle_key(key_event);
+        }
+    }
+}
+
+pub fn handle_add_account(key_event: KeyEvent, app: &mut App) -> Result<(), TotpError> {
+    let Some(form) = app.state.add_form.as_mut() else {
+        app.state.input_mode = InputMode::Normal;
+        return Ok(());
+    };
+    match (key_event.code, key_event.modifiers) {
+        (KeyCode::Esc, _) => app.cancel_add_account(),
+        (KeyCode::Enter, _) if form.is_last() => app.save_add_account()?,
+        (KeyCode::Char('s'), KeyModifiers::CONTROL) => app.save_add_account()?,
+        (KeyCode::Enter | KeyCode::Tab | KeyCode::Down, _) => form.next(),
+        (KeyCode::BackTab | KeyCode::Up, _) => form.previous(),
+        _ => {
+            form.input_mut().handle_key(key_event);
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_locked(key_event: KeyEvent, app: &mut App) -> Result<(), TotpError> {
+    match key_event.code {
+        KeyCode::Enter => app.unlock()?,
+        KeyCode::Esc => app.state.unlock_input = Input::default(),
+        _ => {
+            app.state.unlock_input.handle_key(key_event);
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_edit_confirmation(key_event: KeyEvent, app: &mut App) -> Result<(), TotpError> {
+    match key_event.code {
+        KeyCode::Char('y') => app.save_detail()?,
+        _ => {
+            app.state.input_mode = InputMode::EditDetail;
+            app.state.show_popup = None;
+        }
+    }
+    Ok(())
+}
+
+/// Pasted text goes into whichever input is active
+pub fn handle_paste(text: String, app: &mut App) {
+    app.touch();
+    match app.state.input_mode {
+        InputMode::EditDetail => {
+            let text = if app.state.detail_input_type == DetailInputType::Note {
+                text
+            } else {
+                text.replace(['\r', '\n'], "")
+            };
+            app.state.detail_input.insert_str(&text);
+        }
+        InputMode::AddAccount => {
+            if let Some(form) = app.state.add_form.as_mut() {
+                form.paste(&text);
+            }
+        }
+        InputMode::Locked => app
+            .state
+            .unlock_input
+            .insert_str(&text.replace(['\r', '\n'], "")),
+        InputMode::FilterList => {
+            app.state
+                .filter_input
+                .push_str(&text.replace(['\r', '\n'], ""));
+            app.table_state.select(None);
+        }
         _ => {}
     }
 }
@@ -83,7 +205,7 @@ pub fn handle_input_mode(key_event: KeyEvent, app: &mut App) {
             app.state.filter_input.pop();
             app.table_state.select(None);
         }
-        (KeyCode::Char(c), KeyModifiers::NONE) => {
+        (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
             app.state.filter_input.push(c);
             app.table_state.select(None);
         }
@@ -119,3 +241,78 @@ pub fn handle_delete_confirmation(key_event: KeyEvent, app: &mut App) -> Result<
     }
     Ok(())
 }
+
+pub fn handle_qr_code(app: &mut App) {
+    app.state.input_mode = InputMode::Normal;
+    app.state.show_popup = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::db::encryption::Encryption;
+    use crate::db::models::record::Record;
+    use crate::db::storage::sqlite::SqliteStorage;
+    use crate::db::storage::StorageTrait;
+    use crate::db::Db;
+    use crate::ui::event_handler::EventHandler;
+    use crate::Token;
+    use std::str::FromStr;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tui::backend::TestBackend;
+    use tui::Terminal;
+
+    fn press(tui: &mut Tui<TestBackend>, app: &mut App, code: KeyCode) {
+        handle_key_events(KeyEvent::new(code, KeyModifiers::NONE), tui, app).unwrap();
+    }
+
+    #[test]
+    fn help_over_delete_confirmation() {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+        let db = Db::new(
+            "password".to_string(),
+            Some(format!("file:handlerdb{}?mode=memory&cache=shared", nanos)),
+        )
+        .unwrap();
+        db.init().unwrap();
+        let mut storage = SqliteStorage::new(db, Encryption::default());
+        storage
+            .add_account(Record {
+                account: Some("Account1".to_string()),
+                token: Some(Token::from_str("JBSWY3DPEHPK3PXP").unwrap()),
+                ..Record::default()
+            })
+            .unwrap();
+        let mut app = App::new(storage, &Config::default()).unwrap();
+        let terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        let mut tui = Tui::new(terminal, EventHandler::detached());
+        tui.draw(&mut app).unwrap();
+        app.table_state.select(Some(0));
+
+        press(&mut tui, &mut app, KeyCode::Char('d'));
+        assert!(app.state.input_mode == InputMode::DeleteConfirmation);
+        // The help opens over the popup and closing it goes back to the question
+        press(&mut tui, &mut app, KeyCode::Char('?'));
+        assert!(app.state.show_help);
+        assert!(app.state.input_mode == InputMode::DeleteConfirmation);
+        press(&mut tui, &mut app, KeyCode::Esc);
+        assert!(!app.state.show_help);
+        assert!(app.state.input_mode == InputMode::DeleteConfirmation);
+        assert_eq!(app.state.records.len(), 1);
+        press(&mut tui, &mut app, KeyCode::Char('y'));
+        assert!(app.state.input_mode == InputMode::Normal);
+        assert!(app.state.records.is_empty());
+
+        // While typing '?' is a character and F1 opens the help
+        app.start_add_account();
+        press(&mut tui, &mut app, KeyCode::Char('?'));
+        assert!(!app.state.show_help);
+        press(&mut tui, &mut app, KeyCode::F(1));
+        assert!(app.state.show_help);
+        assert!(app.state.input_mode == InputMode::AddAccount);
+    }
+}
@@ -0,0 +1,146 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// A single line text input with a cursor, counted in characters
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Input {
+    value: String,
+    cursor: usize,
+}
+
+impl Input {
+    /// Start with `value` and the cursor at its end
+    pub fn new(value: String) -> Self {
+        let cursor = value.chars().count();
+        Self { value, cursor }
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn insert(&mut self, c: char) {
+        let index = self.byte_index();
+        self.value.insert(index, c);
+        self.cursor += 1;
+    }
+
+    /// Insert pasted text at the cursor, terminals paste line breaks as carriage returns
+    pub fn insert_str(&mut self, text: &str) {
+        for c in text.replace("\r\n", "\n").replace('\r', "\n").chars() {
+            self.insert(c);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.value.remove(self.byte_index());
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.value.chars().count() {
+            self.value.remove(self.byte_index());
+        }
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = usize::min(self.cursor + 1, self.value.chars().count());
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.value.chars().count();
+    }
+
+    /// Apply an editing key, returning whether it was one
+    pub fn handle_key(&mut self, key_event: KeyEvent) -> bool {
+        match (key_event.code, key_event.modifiers) {
+            (KeyCode::Left, _) => self.left(),
+            (KeyCode::Right, _) => self.right(),
+            (KeyCode::Home, _) | (KeyCode::Char('a'), KeyModifiers::CONTROL) => self.home(),
+            (KeyCode::End, _) | (KeyCode::Char('e'), KeyModifiers::CONTROL) => self.end(),
+            (KeyCode::Backspace, _) => self.backspace(),
+            (KeyCode::Delete, _) => self.delete(),
+            (KeyCode::Char('u'), KeyModifiers::CONTROL) => {
+                self.value = self.value.chars().skip(self.cursor).collect();
+                self.cursor = 0;
+            }
+            (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => self.insert(c),
+            _ => return false,
+        }
+        true
+    }
+
+    /// The part of the value that fits in `width` columns with the cursor visible,
+    /// and the cursor's offset into it
+    pub fn visible(&self, width: usize) -> (String, usize) {
+        let width = usize::max(width, 1);
+        let start = (self.cursor + 1).saturating_sub(width);
+        let visible = self
+            .value
+            .chars()
+            .skip(start)
+            .take(width)
+            // Line breaks would move the rest of the value off the input line
+            .map(|c| if c == '\n' { '↵' } else { c })
+            .collect();
+        (visible, self.cursor - start)
+    }
+
+    fn byte_index(&self) -> usize {
+        self.value
+            .char_indices()
+            .nth(self.cursor)
+            .map(|(index, _)| index)
+            .unwrap_or(self.value.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn edit() {
+        let mut input = Input::new("héllo".to_string());
+        assert_eq!(input.cursor, 5);
+        input.left();
+        input.left();
+        input.insert('X');
+        assert_eq!(input.value(), "hélXlo");
+        input.home();
+        input.delete();
+        input.backspace();
+        assert_eq!(input.value(), "élXlo");
+        input.end();
+        input.insert_str("\r\nworld\r!");
+        assert_eq!(input.value(), "élXlo\nworld\n!");
+        assert!(input.handle_key(KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT)));
+        assert!(input.handle_key(key(KeyCode::Left)));
+        assert!(input.handle_key(KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL)));
+        assert_eq!(input.value(), "A");
+        assert_eq!(input.cursor, 0);
+        assert!(!input.handle_key(key(KeyCode::Enter)));
+    }
+
+    #[test]
+    fn visible() {
+        let mut input = Input::new("abc\ndefgh".to_string());
+        assert_eq!(input.visible(20), ("abc↵defgh".to_string(), 9));
+        assert_eq!(input.visible(4), ("fgh".to_string(), 3));
+        input.home();
+        assert_eq!(input.visible(4), ("abc↵".to_string(), 0));
+    }
+}
@@ -0,0 +1,536 @@
+use crate::ui::state::InputMode;
+use crate::TotpError;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Something a key can be bound to outside of the text inputs
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Down,
+    Up,
+    Top,
+    Bottom,
+    TogglePane,
+    Copy,
+    CopyNext,
+    Filter,
+    Edit,
+    Add,
+    Delete,
+    NextHotp,
+    Qr,
+    ToggleNext,
+    Help,
+    Quit,
+}
+
+impl Action {
+    /// The name used in the config file
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Down => "down",
+            Action::Up => "up",
+            Action::Top => "top",
+            Action::Bottom => "bottom",
+            Action::TogglePane => "toggle_pane",
+            Action::Copy => "copy",
+            Action::CopyNext => "copy_next",
+            Action::Filter => "filter",
+            Action::Edit => "edit",
+            Action::Add => "add",
+            Action::Delete => "delete",
+            Action::NextHotp => "next_hotp",
+            Action::Qr => "qr",
+            Action::ToggleNext => "toggle_next",
+            Action::Help => "help",
+            Action::Quit => "quit",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Down => "Select the next account or detail",
+            Action::Up => "Select the previous account or detail",
+            Action::Top => "Select the first account or detail",
+            Action::Bottom => "Select the last account or detail",
+            Action::TogglePane => "Switch between the accounts and details",
+            Action::Copy => "Copy the OTP or detail",
+            Action::CopyNext => "Copy the next OTP",
+            Action::Filter => "Search the accounts",
+            Action::Edit => "Edit the selected detail",
+            Action::Add => "Add an account",
+            Action::Delete => "Delete the selected account",
+            Action::NextHotp => "Generate the next HOTP code",
+            Action::Qr => "Show the account as a QR code",
+            Action::ToggleNext => "Show or hide the next codes",
+            Action::Help => "Show the key bindings",
+            Action::Quit => "Quit",
+        }
+    }
+}
+
+/// The built in bindings, in the order the help lists them
+const DEFAULT_BINDINGS: [(Action, &[&str]); 16] = [
+    (Action::Down, &["Down"]),
+    (Action::Up, &["Up"]),
+    (Action::Top, &["Home"]),
+    (Action::Bottom, &["End"]),
+    (Action::TogglePane, &["Tab"]),
+    (Action::Copy, &["Enter"]),
+    (Action::CopyNext, &["C"]),
+    (Action::Filter, &["/"]),
+    (Action::Edit, &["e"]),
+    (Action::Add, &["a"]),
+    (Action::Delete, &["d"]),
+    (Action::NextHotp, &["n"]),
+    (Action::Qr, &["r"]),
+    (Action::ToggleNext, &["N"]),
+    (Action::Help, &["?", "F1"]),
+    (Action::Quit, &["q"]),
+];
+
+/// Added to the defaults by the vim preset
+const VIM_BINDINGS: [(Action, &str); 4] = [
+    (Action::Down, "j"),
+    (Action::Up, "k"),
+    (Action::Top, "g"),
+    (Action::Bottom, "G"),
+];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    #[default]
+    Default,
+    Vim,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// The `[keymap]` section of the config file
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeymapConfig {
+    pub preset: Preset,
+    /// Replaces every key of an action, e.g. `copy = ["Enter", "y"]`
+    pub bindings: BTreeMap<Action, Keys>,
+}
+
+/// A key and the modifiers held with it, written like `Ctrl-u`, `Enter` or `G`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    pub fn matches(&self, key_event: &KeyEvent) -> bool {
+        // Shift is already part of the character, and of BackTab
+        let modifiers = match key_event.code {
+            KeyCode::Char(_) | KeyCode::BackTab => key_event.modifiers - KeyModifiers::SHIFT,
+            _ => key_event.modifiers,
+        };
+        self.code == key_event.code && self.modifiers == modifiers
+    }
+
+    /// A plain character, which the text inputs would rather type
+    pub fn is_char(&self) -> bool {
+        matches!(self.code, KeyCode::Char(_)) && self.modifiers.is_empty()
+    }
+}
+
+impl FromStr for Key {
+    type Err = TotpError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut name = s;
+        while let Some((prefix, rest)) = name.split_once('-').filter(|(_, rest)| !rest.is_empty()) {
+            match prefix.to_lowercase().as_str() {
+                "ctrl" => modifiers |= KeyModifiers::CONTROL,
+                "alt" => modifiers |= KeyModifiers::ALT,
+                "shift" => modifiers |= KeyModifiers::SHIFT,
+                _ => break,
+            }
+            name = rest;
+        }
+        let mut chars = name.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) if modifiers.contains(KeyModifiers::SHIFT) => {
+                modifiers -= KeyModifiers::SHIFT;
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match name.to_lowercase().as_str() {
+                "enter" => KeyCode::Enter,
+                "esc" => KeyCode::Esc,
+                "tab" if modifiers.contains(KeyModifiers::SHIFT) => {
+                    modifiers -= KeyModifiers::SHIFT;
+                    KeyCode::BackTab
+                }
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "space" => KeyCode::Char(' '),
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                function => match function.strip_prefix('f').map(u8::from_str) {
+                    Some(Ok(n)) if (1..=12).contains(&n) => KeyCode::F(n),
+                    _ => return Err(TotpError::Config(format!("Unknown key '{}'", s))),
+                },
+            },
+        };
+        Ok(Key { code, modifiers })
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "Shift-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            KeyCode::BackTab => write!(f, "Shift-Tab"),
+            KeyCode::PageUp => write!(f, "PageUp"),
+            KeyCode::PageDown => write!(f, "PageDown"),
+            KeyCode::Delete => write!(f, "Del"),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// The keys bound to each action, Ctrl-c always quits
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    bindings: Vec<(Action, Vec<Key>)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::from_config(&KeymapConfig::default()).expect("the default keymap is valid")
+    }
+}
+
+impl Keymap {
+    /// The preset's bindings with the configured actions replaced, failing on keys bound twice
+    pub fn from_config(config: &KeymapConfig) -> Result<Self, TotpError> {
+        let mut bindings = vec![];
+        for (action, keys) in DEFAULT_BINDINGS {
+            let mut keys = match config.bindings.get(&action) {
+                Some(Keys::One(key)) => vec![key.as_str()],
+                Some(Keys::Many(keys)) => keys.iter().map(String::as_str).collect(),
+                None if config.preset == Preset::Vim => {
+                    let mut keys = keys.to_vec();
+                    keys.extend(
+                        VIM_BINDINGS
+                            .iter()
+                            .filter(|(vim_action, _)| *vim_action == action)
+                            .map(|(_, key)| *key),
+                    );
+                    keys
+                }
+                None => keys.to_vec(),
+            }
+            .into_iter()
+            .map(Key::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+            keys.dedup();
+            bindings.push((action, keys));
+        }
+        let keymap = Keymap { bindings };
+        for (action, keys) in &keymap.bindings {
+            for key in keys {
+                if let Some((other, _)) = keymap
+                    .bindings
+                    .iter()
+                    .find(|(other, keys)| other != action && keys.contains(key))
+                {
+                    return Err(TotpError::Config(format!(
+                        "'{}' is bound to both {} and {}",
+                        key,
+                        action.name(),
+                        other.name()
+                    )));
+                }
+            }
+        }
+        Ok(keymap)
+    }
+
+    pub fn action(&self, key_event: &KeyEvent) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, keys)| keys.iter().any(|key| key.matches(key_event)))
+            .map(|(action, _)| *action)
+    }
+
+    pub fn keys(&self, action: Action) -> &[Key] {
+        self.bindings
+            .iter()
+            .find(|(other, _)| *other == action)
+            .map(|(_, keys)| keys.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// The keys and what they do in `mode`
+    pub fn help(&self, mode: &InputMode) -> Vec<(String, String)> {
+        let bound = |actions: &[Action], typing: bool| {
+            actions
+                .iter()
+                .filter_map(|action| {
+                    let keys = self
+                        .keys(*action)
+                        .iter()
+                        // Plain characters are typed into the input instead
+                        .filter(|key| !typing || !key.is_char())
+                        .map(|key| key.to_string())
+                        .collect::<Vec<_>>();
+                    (!keys.is_empty()).then(|| (keys.join(" / "), action.description().to_string()))
+                })
+                .collect::<Vec<_>>()
+        };
+        let fixed = |bindings: &[(&str, &str)]| {
+            bindings
+                .iter()
+                .map(|(key, description)| (key.to_string(), description.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let mut help = match mode {
+            InputMode::Normal => bound(&DEFAULT_BINDINGS.map(|(action, _)| action), false),
+            InputMode::FilterList => {
+                let mut help = fixed(&[
+                    ("Type", "Filter the accounts"),
+                    ("Backspace", "Delete the last character"),
+                    ("Esc", "Clear the filter and stop searching"),
+                ]);
+                help.extend(bound(
+                    &[
+                        Action::Down,
+                        Action::Up,
+                        Action::Top,
+                        Action::Bottom,
+                        Action::TogglePane,
+                        Action::Copy,
+                        Action::Help,
+                    ],
+                    true,
+                ));
+                help
+            }
+            InputMode::EditDetail => {
+                let mut help = fixed(&[
+                    ("Left / Right", "Move the cursor"),
+                    ("Home / Ctrl-a", "Move to the start"),
+                    ("End / Ctrl-e", "Move to the end"),
+                    ("Backspace / Del", "Delete before / after the cursor"),
+                    ("Ctrl-u", "Delete everything before the cursor"),
+                    ("Enter", "Save, after confirming with 'y'"),
+                    ("Esc", "Cancel"),
+                ]);
+                help.extend(bound(&[Action::Help], true));
+                help
+            }
+            InputMode::AddAccount => {
+                let mut help = fixed(&[
+                    ("Tab / Down", "Next field"),
+                    ("Shift-Tab / Up", "Previous field"),
+                    ("Enter", "Next field, or save on the last field"),
+                    ("Ctrl-s", "Save"),
+                    ("Esc", "Cancel"),
+                ]);
+                help.extend(bound(&[Action::Help], true));
+                help
+            }
+            InputMode::EditConfirmation => {
+                let mut help =
+                    fixed(&[("y", "Save the detail"), ("Any other key", "Keep editing")]);
+                help.extend(bound(&[Action::Help], true));
+                help
+            }
+            InputMode::DeleteConfirmation => {
+                let mut help = fixed(&[("y", "Delete the account"), ("Any other key", "Cancel")]);
+                help.extend(bound(&[Action::Help], false));
+                help
+            }
+            InputMode::QrCode => {
+                let mut help = fixed(&[("Any other key", "Close the QR code")]);
+                help.extend(bound(&[Action::Help], false));
+                help
+            }
+            InputMode::Locked => {
+                let mut help = fixed(&[
+                    ("Enter", "Unlock with the password"),
+                    ("Esc", "Clear the password"),
+                ]);
+                help.extend(bound(&[Action::Help], true));
+                help
+            }
+        };
+        help.push(("Ctrl-c".to_string(), Action::Quit.description().to_string()));
+        help
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn parse_keys() {
+        for (text, code, modifiers, display) in [
+            ("q", KeyCode::Char('q'), KeyModifiers::NONE, "q"),
+            ("G", KeyCode::Char('G'), KeyModifiers::NONE, "G"),
+            ("shift-g", KeyCode::Char('G'), KeyModifiers::NONE, "G"),
+            (
+                "ctrl-u",
+                KeyCode::Char('u'),
+                KeyModifiers::CONTROL,
+                "Ctrl-u",
+            ),
+            ("-", KeyCode::Char('-'), KeyModifiers::NONE, "-"),
+            (
+                "Ctrl--",
+                KeyCode::Char('-'),
+                KeyModifiers::CONTROL,
+                "Ctrl--",
+            ),
+            ("enter", KeyCode::Enter, KeyModifiers::NONE, "Enter"),
+            (
+                "Shift-Tab",
+                KeyCode::BackTab,
+                KeyModifiers::NONE,
+                "Shift-Tab",
+            ),
+            ("space", KeyCode::Char(' '), KeyModifiers::NONE, "Space"),
+            ("f1", KeyCode::F(1), KeyModifiers::NONE, "F1"),
+            (
+                "alt-pagedown",
+                KeyCode::PageDown,
+                KeyModifiers::ALT,
+                "Alt-PageDown",
+            ),
+        ] {
+            let parsed = Key::from_str(text).unwrap();
+            assert_eq!(parsed, Key { code, modifiers }, "{}", text);
+            assert_eq!(parsed.to_string(), display);
+            assert_eq!(Key::from_str(display).unwrap(), parsed);
+        }
+        assert!(Key::from_str("f13").is_err());
+        assert!(Key::from_str("hyper-x").is_err());
+        assert!(Key::from_str("").is_err());
+    }
+
+    #[test]
+    fn presets_and_overrides() {
+        let keymap = Keymap::default();
+        assert_eq!(
+            keymap.action(&key(KeyCode::Char('G'), KeyModifiers::SHIFT)),
+            None
+        );
+        assert_eq!(
+            keymap.action(&key(KeyCode::Char('N'), KeyModifiers::SHIFT)),
+            Some(Action::ToggleNext)
+        );
+        assert_eq!(
+            keymap.action(&key(KeyCode::Char('q'), KeyModifiers::CONTROL)),
+            None
+        );
+
+        let vim = Keymap::from_config(&KeymapConfig {
+            preset: Preset::Vim,
+            bindings: BTreeMap::from([(
+                Action::Copy,
+                Keys::Many(vec!["y".into(), "Enter".into()]),
+            )]),
+        })
+        .unwrap();
+        assert_eq!(
+            vim.action(&key(KeyCode::Char('j'), KeyModifiers::NONE)),
+            Some(Action::Down)
+        );
+        assert_eq!(
+            vim.action(&key(KeyCode::Char('G'), KeyModifiers::SHIFT)),
+            Some(Action::Bottom)
+        );
+        assert_eq!(
+            vim.action(&key(KeyCode::Char('y'), KeyModifiers::NONE)),
+            Some(Action::Copy)
+        );
+        assert_eq!(vim.keys(Action::Down).len(), 2);
+
+        let clash = Keymap::from_config(&KeymapConfig {
+            bindings: BTreeMap::from([(Action::Copy, Keys::One("e".into()))]),
+            ..KeymapConfig::default()
+        });
+        assert!(matches!(clash, Err(TotpError::Config(_))));
+    }
+
+    #[test]
+    fn help_per_mode() {
+        let keymap = Keymap::from_config(&KeymapConfig {
+            preset: Preset::Vim,
+            ..KeymapConfig::default()
+        })
+        .unwrap();
+        let normal = keymap.help(&InputMode::Normal);
+        assert!(normal.contains(&(
+            "Down / j".to_string(),
+            Action::Down.description().to_string()
+        )));
+        // Characters are typed while searching, only the other keys apply
+        let filter = keymap.help(&InputMode::FilterList);
+        assert!(filter.contains(&("Down".to_string(), Action::Down.description().to_string())));
+        assert!(filter.contains(&("F1".to_string(), Action::Help.description().to_string())));
+        assert!(keymap
+            .help(&InputMode::QrCode)
+            .iter()
+            .any(|(key, _)| key == "Ctrl-c"));
+        // Every mode can open the help, with F1 where characters are typed
+        for (name, mode, keys) in [
+            ("delete", InputMode::DeleteConfirmation, "? / F1"),
+            ("qr", InputMode::QrCode, "? / F1"),
+            ("confirm", InputMode::EditConfirmation, "F1"),
+            ("edit", InputMode::EditDetail, "F1"),
+            ("add", InputMode::AddAccount, "F1"),
+            ("locked", InputMode::Locked, "F1"),
+        ] {
+            assert!(
+                keymap
+                    .help(&mode)
+                    .contains(&(keys.to_string(), Action::Help.description().to_string())),
+                "{}",
+                name
+            );
+        }
+    }
+}
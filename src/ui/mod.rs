@@ -1,18 +1,22 @@
+use crate::config::Config;
 use crate::{App, Event, EventHandler, StorageTrait, TotpError, Tui};
 use ::tui::backend::CrosstermBackend;
 use ::tui::Terminal;
 use std::io;
 
+pub mod add_form;
 pub mod app;
 pub mod clip;
 pub mod event_handler;
 pub mod handler;
+pub mod input;
+pub mod keymap;
 mod state;
 pub mod tui;
 pub mod widgets;
 
-pub fn init<T: StorageTrait + 'static>(storage: T) -> Result<(), TotpError> {
-    let mut app: App = App::new(storage)?;
+pub fn init<T: StorageTrait + 'static>(storage: T, config: &Config) -> Result<(), TotpError> {
+    let mut app: App = App::new(storage, config)?;
     let backend = CrosstermBackend::new(io::stdout());
     let terminal = Terminal::new(backend)?;
     let events = EventHandler::new(250)?;
@@ -23,10 +27,13 @@ pub fn init<T: StorageTrait + 'static>(storage: T) -> Result<(), TotpError> {
         tui.draw(&mut app)?;
         match tui.events.next()? {
             Event::Key(key_event) => handler::handle_key_events(key_event, &mut tui, &mut app)?,
+            Event::Paste(text) => handler::handle_paste(text, &mut app),
             Event::Tick => app.tick(),
             _ => {}
         }
     }
+    // Leave nothing copied behind
+    app.clear_copied();
     tui.exit()?;
     Ok(())
 }
@@ -1,7 +1,11 @@
 #![allow(dead_code)]
 use crate::db::models::record::AccountName;
+use crate::ui::add_form::AddForm;
+use crate::ui::input::Input;
 use crate::ui::widgets::popup::Popup;
 use crate::{Generator, Record, StorageTrait, TotpError};
+use chrono::NaiveDateTime;
+use std::collections::HashMap;
 
 pub type TotpAccountName = String;
 pub type TotpCode = String;
@@ -14,7 +18,13 @@ pub enum InputMode {
     Normal,
     FilterList,
     EditDetail,
+    /// Waiting for 'y' to save the edited detail
+    EditConfirmation,
     DeleteConfirmation,
+    QrCode,
+    AddAccount,
+    /// Idle for too long, the password is needed to continue
+    Locked,
 }
 
 #[derive(PartialEq, Eq, Default)]
@@ -24,24 +34,88 @@ pub enum ActivePane {
     DetailView,
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DetailInputType {
     Password,
     Username,
     Note,
 }
 
+impl DetailInputType {
+    /// The detail shown at `index` in the details pane
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => DetailInputType::Username,
+            2 => DetailInputType::Note,
+            _ => DetailInputType::Password,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        match self {
+            DetailInputType::Password => 0,
+            DetailInputType::Username => 1,
+            DetailInputType::Note => 2,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DetailInputType::Password => "password",
+            DetailInputType::Username => "username",
+            DetailInputType::Note => "note",
+        }
+    }
+
+    pub fn value(&self, record: &Record) -> Option<String> {
+        match self {
+            DetailInputType::Password => record.password.clone(),
+            DetailInputType::Username => record.user.clone(),
+            DetailInputType::Note => record.note.clone(),
+        }
+    }
+
+    /// Set this detail on `record`, an empty value removes it
+    pub fn set(&self, record: &mut Record, value: &str) {
+        let value = Some(value.to_string()).filter(|value| !value.is_empty());
+        match self {
+            DetailInputType::Password => record.password = value,
+            DetailInputType::Username => record.user = value,
+            DetailInputType::Note => record.note = value,
+        }
+    }
+}
+
+/// A value copied to the clipboard, cleared at `clear_at` if nothing replaced it
+pub struct Copied {
+    pub value: String,
+    pub clear_at: NaiveDateTime,
+    pub title: String,
+    pub message: String,
+}
+
 pub struct State {
     pub input_mode: InputMode,
     pub active_pane: ActivePane,
     pub detail_input_type: DetailInputType,
-    pub detail_input: String,
+    pub detail_input: Input,
+    pub add_form: Option<AddForm>,
+    /// The password typed on the lock screen
+    pub unlock_input: Input,
+    pub unlock_error: Option<String>,
     pub filter_input: String,
     pub items: Vec<(AccountName, Option<Generator>, RecordId)>,
     pub records: Vec<Record>,
     pub display_otps: Vec<(TotpAccountName, TotpCode, ExpirySeconds, RecordId)>,
+    /// The last code generated for each HOTP record this session
+    pub hotp_codes: HashMap<RecordId, TotpCode>,
     pub running: bool,
     pub show_popup: Option<Popup>,
+    pub copied: Option<Copied>,
+    /// The key bindings of the current input mode are shown over everything else
+    pub show_help: bool,
+    /// Show the code that follows the current one
+    pub show_next_code: bool,
     pub storage: Option<Box<dyn StorageTrait + 'static>>,
 }
 
@@ -51,13 +125,20 @@ impl Default for State {
             input_mode: InputMode::default(),
             active_pane: ActivePane::default(),
             detail_input_type: DetailInputType::Password,
-            detail_input: String::new(),
+            detail_input: Input::default(),
+            add_form: None,
+            unlock_input: Input::default(),
+            unlock_error: None,
             filter_input: String::new(),
             items: vec![],
             records: vec![],
             display_otps: vec![],
+            hotp_codes: HashMap::new(),
             running: true,
             show_popup: None,
+            copied: None,
+            show_help: false,
+            show_next_code: false,
             storage: None,
         }
     }
@@ -1,8 +1,11 @@
 use crate::ui::app::App;
 use crate::ui::event_handler::EventHandler;
-use crate::ui::widgets::{details_view, filter_input, otp_table};
+use crate::ui::state::InputMode;
+use crate::ui::widgets::{add_form, details_view, filter_input, help, lock_screen, otp_table};
 use crate::TotpError;
-use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::event::{
+    DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+};
 use crossterm::terminal;
 use crossterm::terminal::{disable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
 use std::io;
@@ -22,14 +25,25 @@ impl<B: Backend> Tui<B> {
 
     pub fn init(&mut self) -> Result<(), TotpError> {
         terminal::enable_raw_mode()?;
-        crossterm::execute!(std::io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+        crossterm::execute!(
+            std::io::stdout(),
+            EnterAlternateScreen,
+            EnableMouseCapture,
+            EnableBracketedPaste
+        )?;
         self.terminal.hide_cursor()?;
         self.terminal.clear()?;
         let original_hook = std::panic::take_hook();
 
         std::panic::set_hook(Box::new(move |panic| {
             disable_raw_mode().unwrap();
-            crossterm::execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture).unwrap();
+            crossterm::execute!(
+                io::stdout(),
+                LeaveAlternateScreen,
+                DisableMouseCapture,
+                DisableBracketedPaste
+            )
+            .unwrap();
             original_hook(panic);
         }));
         Ok(())
@@ -44,13 +58,25 @@ impl<B: Backend> Tui<B> {
 
     pub fn exit(&mut self) -> Result<(), TotpError> {
         terminal::disable_raw_mode()?;
-        crossterm::execute!(std::io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
+        crossterm::execute!(
+            std::io::stdout(),
+            LeaveAlternateScreen,
+            DisableMouseCapture,
+            DisableBracketedPaste
+        )?;
         self.terminal.show_cursor()?;
         Ok(())
     }
 }
 
 fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>) {
+    if app.state.input_mode == InputMode::Locked {
+        lock_screen::render(app, frame, frame.size());
+        if app.state.show_help {
+            help::render(app, frame, frame.size());
+        }
+        return;
+    }
     let rects = Layout::default()
         .direction(Direction::Vertical)
         .constraints([Constraint::Length(3), Constraint::Min(1)].as_ref())
@@ -64,8 +90,14 @@ fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>) {
         .split(rects[1]);
     otp_table::render(app, frame, body_rects[0]);
     details_view::render(app, frame, body_rects[1]);
+    if app.state.input_mode == InputMode::AddAccount {
+        add_form::render(app, frame, frame.size());
+    }
     if let Some(popup) = app.state.show_popup.as_ref() {
         let rect = frame.size();
         popup.render(frame, rect);
     }
+    if app.state.show_help {
+        help::render(app, frame, frame.size());
+    }
 }
@@ -0,0 +1,108 @@
+use crate::ui::add_form::AddField;
+use crate::ui::app::App;
+use crate::ui::widgets::popup::{Popup, Size};
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::Paragraph;
+use tui::Frame;
+
+/// Width of the field labels, including the selection marker
+const LABEL_WIDTH: usize = 12;
+/// Lines above the first field
+const HEADER_LINES: u16 = 2;
+
+pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: Rect) {
+    let Some(form) = app.state.add_form.as_ref() else {
+        return;
+    };
+    let popup = Popup::new(
+        "Add Account".to_string(),
+        None,
+        None,
+        Some(true),
+        None,
+        Some(Size { x: 70, y: 50 }),
+        None,
+    );
+    let area = popup.centered_rect(popup.size.clone().unwrap_or_default(), rect);
+    // Inside the borders
+    let width = area.width.saturating_sub(2) as usize;
+    let dim = Style::default().add_modifier(Modifier::DIM);
+
+    let mut lines = vec![
+        Spans::from(Span::styled(
+            "Paste an otpauth:// URI to fill in every field",
+            dim,
+        )),
+        Spans::default(),
+    ];
+    for (index, field) in AddField::ALL.iter().enumerate() {
+        let selected = index == form.selected;
+        let error = form.field_error(*field);
+        let error_width = error.as_ref().map(|e| e.chars().count() + 2).unwrap_or(0);
+        let input = form.input(*field);
+        let (mut value, cursor) =
+            input.visible(width.saturating_sub(LABEL_WIDTH + error_width + 1));
+        if *field == AddField::Password {
+            value = "*".repeat(value.chars().count());
+        }
+        let label_style = if selected {
+            Style::default()
+                .fg(Color::Green)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        let marker = if selected { "> " } else { "  " };
+        let mut spans = vec![
+            Span::styled(
+                format!(
+                    "{}{: <width$}",
+                    marker,
+                    format!("{}:", field.label()),
+                    width = LABEL_WIDTH - 2
+                ),
+                label_style,
+            ),
+            Span::styled(value, Style::default().fg(Color::Yellow)),
+        ];
+        // Only complain about empty fields once they have been visited
+        if let Some(error) = error.filter(|_| selected || !input.value().is_empty()) {
+            spans.push(Span::styled(
+                format!("  {}", error),
+                Style::default().fg(Color::Red),
+            ));
+        }
+        lines.push(Spans::from(spans));
+        if selected {
+            frame.set_cursor(
+                area.x + 1 + (LABEL_WIDTH + cursor) as u16,
+                area.y + 1 + HEADER_LINES + index as u16,
+            );
+        }
+    }
+    lines.push(Spans::from(Span::styled(
+        format!(
+            "  {: <width$}{} {}",
+            "Type:",
+            form.template().otp_type,
+            form.template().algorithm,
+            width = LABEL_WIDTH - 2
+        ),
+        dim,
+    )));
+    lines.push(Spans::default());
+    if let Some(error) = form.error.as_ref() {
+        lines.push(Spans::from(Span::styled(
+            error.clone(),
+            Style::default().fg(Color::Red),
+        )));
+    }
+    lines.push(Spans::from(Span::styled(
+        "Tab next, Shift-Tab back, Ctrl-s or Enter on Step saves, Esc cancels",
+        dim,
+    )));
+    popup.render_paragraph(frame, rect, Paragraph::new(lines));
+}
@@ -1,5 +1,5 @@
 use crate::ui::app::App;
-use crate::ui::state::ActivePane;
+use crate::ui::state::{ActivePane, DetailInputType, InputMode};
 use tui::backend::Backend;
 use tui::layout::Rect;
 use tui::style::{Color, Modifier, Style};
@@ -28,19 +28,38 @@ pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: Rect) {
 
                 let hidden = app.state.active_pane != ActivePane::DetailView;
                 let frame_size = frame.size().width as usize;
-                list_items.push(list_item(
-                    "Password",
-                    record.password.clone(),
-                    frame_size,
-                    hidden,
-                ));
-                list_items.push(list_item(
-                    "Username",
-                    record.user.clone(),
-                    frame_size,
-                    hidden,
-                ));
-                list_items.push(list_item("Note", record.note.clone(), frame_size, hidden));
+                // Borders and the highlight symbol
+                let width = rect.width.saturating_sub(5) as usize;
+                let editing = matches!(
+                    app.state.input_mode,
+                    InputMode::EditDetail | InputMode::EditConfirmation
+                );
+                for (index, title) in ["Password", "Username", "Note"].iter().enumerate() {
+                    let input_type = DetailInputType::from_index(index);
+                    if editing && app.state.detail_input_type == input_type {
+                        let (visible, cursor) = app.state.detail_input.visible(width);
+                        if app.state.input_mode == InputMode::EditDetail {
+                            frame.set_cursor(
+                                rect.x + 3 + cursor as u16,
+                                // Each detail takes a title, value and separator line
+                                rect.y + 2 + index as u16 * 3,
+                            );
+                        }
+                        list_items.push(list_item(
+                            &format!("{} (editing)", title),
+                            Some(visible),
+                            frame_size,
+                            false,
+                        ));
+                    } else {
+                        list_items.push(list_item(
+                            title,
+                            input_type.value(record),
+                            frame_size,
+                            hidden,
+                        ));
+                    }
+                }
             }
         }
     }
@@ -55,7 +74,12 @@ pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: Rect) {
     frame.render_stateful_widget(list.block(block), rect, &mut app.detail_state);
 }
 
-fn list_item(title: &str, value: Option<String>, frame_size: usize, hidden: bool) -> ListItem {
+fn list_item(
+    title: &str,
+    value: Option<String>,
+    frame_size: usize,
+    hidden: bool,
+) -> ListItem<'static> {
     let mut title_text = Text::styled(
         format!("{}:\n", title),
         Style::default().add_modifier(Modifier::DIM),
@@ -0,0 +1,45 @@
+use crate::ui::app::App;
+use crate::ui::widgets::popup::{Popup, Size};
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::Paragraph;
+use tui::Frame;
+
+/// The key bindings of the current input mode
+pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: Rect) {
+    let help = app.keymap.help(&app.state.input_mode);
+    let key_width = help
+        .iter()
+        .map(|(keys, _)| keys.chars().count())
+        .max()
+        .unwrap_or_default();
+    let mut lines = help
+        .into_iter()
+        .map(|(keys, description)| {
+            Spans::from(vec![
+                Span::styled(
+                    format!(" {: <width$}  ", keys, width = key_width),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::raw(description),
+            ])
+        })
+        .collect::<Vec<_>>();
+    lines.push(Spans::default());
+    lines.push(Spans::from(Span::styled(
+        " Press any key to close",
+        Style::default().add_modifier(Modifier::DIM),
+    )));
+    let popup = Popup::new(
+        "Key Bindings".to_string(),
+        None,
+        None,
+        Some(true),
+        None,
+        Some(Size { x: 70, y: 80 }),
+        None,
+    );
+    popup.render_paragraph(frame, rect, Paragraph::new(lines));
+}
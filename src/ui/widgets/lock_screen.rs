@@ -0,0 +1,54 @@
+use crate::ui::app::App;
+use crate::ui::widgets::popup::{Popup, Size};
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::Paragraph;
+use tui::Frame;
+
+const PROMPT: &str = "Password: ";
+
+/// Covers the whole screen until the password is entered
+pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: Rect) {
+    let popup = Popup::new(
+        "Locked".to_string(),
+        None,
+        None,
+        Some(false),
+        None,
+        Some(Size { x: 50, y: 30 }),
+        None,
+    );
+    let area = popup.centered_rect(popup.size.clone().unwrap_or_default(), rect);
+    let width = area.width.saturating_sub(2) as usize;
+    let (value, cursor) = app
+        .state
+        .unlock_input
+        .visible(width.saturating_sub(PROMPT.len() + 1));
+    let dim = Style::default().add_modifier(Modifier::DIM);
+    let mut lines = vec![
+        Spans::from("Enter the password to unlock"),
+        Spans::default(),
+        Spans::from(vec![
+            Span::raw(PROMPT),
+            Span::styled(
+                "*".repeat(value.chars().count()),
+                Style::default().fg(Color::Yellow),
+            ),
+        ]),
+        Spans::default(),
+    ];
+    if let Some(error) = app.state.unlock_error.as_ref() {
+        lines.push(Spans::from(Span::styled(
+            error.clone(),
+            Style::default().fg(Color::Red),
+        )));
+    }
+    lines.push(Spans::from(Span::styled(
+        "Enter unlocks, Ctrl-c quits",
+        dim,
+    )));
+    frame.set_cursor(area.x + 1 + (PROMPT.len() + cursor) as u16, area.y + 1 + 2);
+    popup.render_paragraph(frame, rect, Paragraph::new(lines));
+}
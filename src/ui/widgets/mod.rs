@@ -1,5 +1,8 @@
+pub mod add_form;
 pub mod clear;
 pub mod details_view;
 pub mod filter_input;
+pub mod help;
+pub mod lock_screen;
 pub mod otp_table;
 pub mod popup;
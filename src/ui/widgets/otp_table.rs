@@ -1,11 +1,16 @@
+use crate::db::search::Query;
 use crate::ui::app::App;
 use crate::ui::state::ActivePane;
 use tui::backend::Backend;
 use tui::layout::{Constraint, Rect};
 use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
 use tui::widgets::{Block, BorderType, Borders, Cell, Row, Table};
 use tui::Frame;
 
+/// Cells in the countdown bar
+const COUNTDOWN_WIDTH: usize = 10;
+
 pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: Rect) {
     let border_type = if app.state.active_pane == ActivePane::OtpTable {
         BorderType::Thick
@@ -18,26 +23,44 @@ pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: Rect) {
         selected_style = selected_style.add_modifier(Modifier::DIM);
     }
     let normal_style = Style::default().bg(Color::Gray);
-    let header_cells = ["Account", "OTP", "Expires In"]
-        .iter()
-        .map(|h| Cell::from(*h).style(Style::default().fg(Color::Blue)));
+    let show_next = app.state.show_next_code;
+    let header_cells = ["Account", "OTP"]
+        .into_iter()
+        .chain(show_next.then_some("Next"))
+        .chain(["Expires In"])
+        .map(|h| Cell::from(h).style(Style::default().fg(Color::Blue)));
     let header = Row::new(header_cells)
         .style(normal_style)
         .height(1)
         .bottom_margin(1);
-    let display_rows = app
+    let query = Query::new(&app.state.filter_input);
+    let mut matches = app
         .state
         .items
         .iter()
-        .filter(|(account_name, _, _record_id)| {
-            app.state.filter_input.is_empty()
-                || account_name
-                    .to_lowercase()
-                    .contains(&app.state.filter_input.to_lowercase())
+        .filter_map(|item| {
+            let record = app.state.records.iter().find(|r| r.id == item.2)?;
+            query.matches(record).map(|m| (item, m))
         })
+        .collect::<Vec<_>>();
+    // Items are sorted by name, which equal scores keep
+    matches.sort_by_key(|(_, m)| std::cmp::Reverse(m.score));
+    let highlights = matches
+        .iter()
+        .map(|(_, m)| m.account.clone())
+        .collect::<Vec<_>>();
+    let display_rows = matches
+        .into_iter()
+        .map(|(item, _)| item)
         .map(|(account_name, generator, record_id)| {
             let (code, expiry) = if let Some(generator) = generator {
-                generator.generate(None).unwrap()
+                if generator.is_hotp() {
+                    // HOTP codes only change when the counter is advanced with 'n'
+                    let code = app.state.hotp_codes.get(record_id).cloned();
+                    (code.unwrap_or_else(|| "-".to_string()), 0)
+                } else {
+                    generator.generate(None).unwrap()
+                }
             } else {
                 ("N/A".to_string(), 0)
             };
@@ -46,11 +69,16 @@ pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: Rect) {
         })
         .collect::<Vec<_>>();
     app.state.display_otps = display_rows.clone();
-    let rows = display_rows
-        .iter()
-        .cloned()
-        .map(|(account_name, code, expiry, _)| {
+    let rows = display_rows.iter().cloned().zip(highlights).map(
+        |((account_name, code, expiry, record_id), highlight)| {
             let height = 1;
+            let generator = app
+                .state
+                .items
+                .iter()
+                .find(|(_, _, id)| *id == record_id)
+                .and_then(|(_, generator, _)| generator.as_ref());
+            let is_hotp = generator.map(|g| g.is_hotp()) == Some(true);
             let color = if expiry > 15 {
                 Color::Green
             } else if expiry > 5 {
@@ -58,13 +86,41 @@ pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: Rect) {
             } else {
                 Color::Red
             };
-            let cells = vec![
-                Cell::from(account_name),
+            let mut cells = vec![
+                Cell::from(highlight_matches(&account_name, &highlight)),
                 Cell::from(code),
-                Cell::from(expiry.to_string()).style(Style::default().fg(color)),
             ];
+            if show_next {
+                let next = generator
+                    .filter(|g| !g.is_hotp())
+                    .and_then(|g| g.generate_next(None).ok())
+                    .map(|(code, _)| code)
+                    .unwrap_or_else(|| "-".to_string());
+                cells.push(Cell::from(next).style(Style::default().add_modifier(Modifier::DIM)));
+            }
+            cells.push(match generator {
+                Some(_) if is_hotp => Cell::from("HOTP").style(Style::default().fg(Color::Blue)),
+                Some(generator) => Cell::from(countdown(expiry, generator.step()))
+                    .style(Style::default().fg(color)),
+                None => Cell::from(""),
+            });
             Row::new(cells).height(height as u16).bottom_margin(0)
-        });
+        },
+    );
+    let code_widths = [8]
+        .into_iter()
+        .chain(show_next.then_some(8))
+        .chain([COUNTDOWN_WIDTH as u16 + 4])
+        .collect::<Vec<u16>>();
+    // The account takes whatever the borders, highlight symbol, codes and column gaps leave
+    let account_width = rect
+        .width
+        .saturating_sub(4 + code_widths.iter().map(|w| w + 1).sum::<u16>());
+    let widths = [account_width]
+        .into_iter()
+        .chain(code_widths)
+        .map(Constraint::Length)
+        .collect::<Vec<_>>();
 
     let t = Table::new(rows)
         .header(header)
@@ -76,13 +132,72 @@ pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: Rect) {
         )
         .highlight_style(selected_style)
         .highlight_symbol("> ")
-        .widths(&[
-            Constraint::Percentage(75),
-            Constraint::Length(6),
-            Constraint::Min(6),
-        ]);
+        .widths(&widths);
     if !app.state.items.is_empty() && app.table_state.selected().is_none() {
         app.table_state.select(Some(0));
     }
     frame.render_stateful_widget(t, rect, &mut app.table_state);
 }
+
+/// `text` with the characters at `positions` picked out
+fn highlight_matches(text: &str, positions: &[usize]) -> Spans<'static> {
+    let style = |matched: bool| {
+        if matched {
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        }
+    };
+    let mut spans: Vec<Span<'static>> = vec![];
+    for (i, c) in text.chars().enumerate() {
+        let style = style(positions.contains(&i));
+        match spans.last_mut() {
+            Some(span) if span.style == style => span.content.to_mut().push(c),
+            _ => spans.push(Span::styled(c.to_string(), style)),
+        }
+    }
+    Spans::from(spans)
+}
+
+/// A bar that empties as the code expires, scaled to the account's own step
+fn countdown(expiry: u64, step: u64) -> String {
+    let step = u64::max(step, 1);
+    let filled = (expiry * COUNTDOWN_WIDTH as u64).div_ceil(step) as usize;
+    let filled = usize::min(filled, COUNTDOWN_WIDTH);
+    format!(
+        "{}{} {:>2}s",
+        "█".repeat(filled),
+        "░".repeat(COUNTDOWN_WIDTH - filled),
+        expiry
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn countdown_scales_to_step() {
+        assert_eq!(countdown(30, 30), "██████████ 30s");
+        assert_eq!(countdown(15, 30), "█████░░░░░ 15s");
+        assert_eq!(countdown(15, 60), "███░░░░░░░ 15s");
+        assert_eq!(countdown(1, 90), "█░░░░░░░░░  1s");
+    }
+
+    #[test]
+    fn highlight() {
+        let spans = highlight_matches("GitHub", &[0, 3, 4]);
+        let parts = spans
+            .0
+            .iter()
+            .map(|span| (span.content.as_ref(), span.style.fg.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parts,
+            vec![("G", true), ("it", false), ("Hu", true), ("b", false)]
+        );
+        assert_eq!(highlight_matches("AWS", &[]).0.len(), 1);
+    }
+}
@@ -14,6 +14,7 @@ pub enum Position {
     Bottom,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for Position {
     fn default() -> Self {
         Self::Center